@@ -0,0 +1,128 @@
+//! Headless router: reads a diagram file, routes every connection and writes the route
+//! polylines as JSON.
+//!
+//! Usage: `box-connector-cli <diagram.json> [-o <routes.json>]`
+//!
+//! The routes are written to standard output unless an output file is given. The exit
+//! status is 1 if any connection could not be routed, and 2 if the input could not be read
+//! or the output could not be written.
+
+use std::process::ExitCode;
+
+use box_connector::{label::LabelPlacement, Diagram, Point};
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct Output {
+    routes: Vec<RouteOutput>,
+    /// Number of places where routes cross each other
+    crossings: usize,
+}
+
+/// Result for a single connection. Exactly one of `polyline` and `error` is present.
+#[derive(Serialize)]
+struct RouteOutput {
+    connection: usize,
+    from: usize,
+    to: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    polyline: Option<Vec<Point>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// Where the connection's label goes, if it has one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<LabelPlacement>,
+}
+
+struct Args {
+    input: String,
+    output: Option<String>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut input = None;
+    let mut output = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => {
+                output = Some(args.next().ok_or("Missing file name after -o")?);
+            }
+            _ if input.is_none() => input = Some(arg),
+            _ => return Err(format!("Unexpected argument: {arg}")),
+        }
+    }
+    Ok(Args {
+        input: input.ok_or("Missing diagram file")?,
+        output,
+    })
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            eprintln!("Usage: box-connector-cli <diagram.json> [-o <routes.json>]");
+            return ExitCode::from(2);
+        }
+    };
+
+    let diagram: Diagram = match std::fs::read_to_string(&args.input)
+        .map_err(|e| e.to_string())
+        .and_then(|text| serde_json::from_str(&text).map_err(|e| e.to_string()))
+    {
+        Ok(diagram) => diagram,
+        Err(e) => {
+            eprintln!("Error: failed to load {}: {e}", args.input);
+            return ExitCode::from(2);
+        }
+    };
+
+    let routed = diagram.route();
+
+    let mut failed = false;
+    let routes = diagram
+        .connections
+        .iter()
+        .zip(routed.routes.iter().zip(routed.polylines()))
+        .zip(routed.labels())
+        .enumerate()
+        .map(|(i, ((connection, (route, polyline)), label))| {
+            let (polyline, error) = match route {
+                Ok(_) => (polyline, None),
+                Err(e) => {
+                    eprintln!("{e}");
+                    failed = true;
+                    (None, Some(e.to_string()))
+                }
+            };
+            RouteOutput {
+                connection: i,
+                from: connection.from,
+                to: connection.to,
+                polyline,
+                error,
+                label,
+            }
+        })
+        .collect();
+
+    let crossings = routed.crossings();
+    let text = serde_json::to_string_pretty(&Output { routes, crossings })
+        .expect("serializing routes should not fail");
+    if let Some(output) = args.output {
+        if let Err(e) = std::fs::write(&output, text) {
+            eprintln!("Error: failed to write {output}: {e}");
+            return ExitCode::from(2);
+        }
+    } else {
+        println!("{text}");
+    }
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
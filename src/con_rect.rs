@@ -1,15 +1,96 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::geom::Point;
+
+/// A side of a [`ConRect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Side {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+impl Side {
+    pub const ALL: [Side; 4] = [Side::Left, Side::Right, Side::Top, Side::Bottom];
+}
+
+/// Where the ports on one side of a [`ConRect`] are.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(untagged))]
+pub enum PortLayout {
+    /// This many ports spread evenly along the side, away from the corners
+    Even(usize),
+    /// Ports at these fractions of the side length, measured from its left or top end.
+    /// Fractions outside 0 to 1 are moved to the nearest end, and ones that are not finite
+    /// are ignored.
+    Offsets(Vec<f32>),
+}
+
+impl Default for PortLayout {
+    fn default() -> Self {
+        Self::Even(1)
+    }
+}
+
+impl PortLayout {
+    /// Fractions of the side length at which the ports are placed.
+    pub fn fractions(&self) -> Vec<f32> {
+        match self {
+            Self::Even(count) => (0..*count)
+                .map(|i| (i + 1) as f32 / (*count + 1) as f32)
+                .collect(),
+            Self::Offsets(offsets) => offsets
+                .iter()
+                .filter(|offset| offset.is_finite())
+                .map(|offset| offset.clamp(0., 1.))
+                .collect(),
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
 /// Connectable rectangle
 ///
 /// Put a weird prefix to avoid name collision with egui's Rect
-pub(crate) struct ConRect {
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ConRect {
     pub x: f32,
     pub y: f32,
     pub width: f32,
     pub height: f32,
-    pub left_con: Option<usize>,
-    pub right_con: Option<usize>,
-    pub top_con: Option<usize>,
-    pub bottom_con: Option<usize>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "PortLayout::is_default")
+    )]
+    pub left_ports: PortLayout,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "PortLayout::is_default")
+    )]
+    pub right_ports: PortLayout,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "PortLayout::is_default")
+    )]
+    pub top_ports: PortLayout,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "PortLayout::is_default")
+    )]
+    pub bottom_ports: PortLayout,
+    // Connectors are grid node ids assigned by `Grid::new`, so they are not saved. They
+    // are in the order of `Side::ALL`, and along each side from the left or top end.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub cons: Vec<(Side, usize)>,
 }
 
 impl ConRect {
@@ -19,21 +100,71 @@ impl ConRect {
             y,
             width,
             height,
-            left_con: None,
-            right_con: None,
-            top_con: None,
-            bottom_con: None,
+            left_ports: PortLayout::default(),
+            right_ports: PortLayout::default(),
+            top_ports: PortLayout::default(),
+            bottom_ports: PortLayout::default(),
+            cons: vec![],
+        }
+    }
+
+    pub fn ports(&self, side: Side) -> &PortLayout {
+        match side {
+            Side::Left => &self.left_ports,
+            Side::Right => &self.right_ports,
+            Side::Top => &self.top_ports,
+            Side::Bottom => &self.bottom_ports,
+        }
+    }
+
+    pub fn ports_mut(&mut self, side: Side) -> &mut PortLayout {
+        match side {
+            Side::Left => &mut self.left_ports,
+            Side::Right => &mut self.right_ports,
+            Side::Top => &mut self.top_ports,
+            Side::Bottom => &mut self.bottom_ports,
         }
     }
 
+    /// Positions of the ports on `side`, `offset` away from it.
+    pub fn port_positions(&self, side: Side, offset: f32) -> Vec<Point> {
+        self.ports(side)
+            .fractions()
+            .into_iter()
+            .map(|f| match side {
+                Side::Left => Point::new(self.x - offset, self.y + f * self.height),
+                Side::Right => Point::new(self.x + self.width + offset, self.y + f * self.height),
+                Side::Top => Point::new(self.x + f * self.width, self.y - offset),
+                Side::Bottom => Point::new(self.x + f * self.width, self.y + self.height + offset),
+            })
+            .collect()
+    }
+
+    /// Returns true if `pos` is inside this rect expanded by `margin` on every side.
+    pub fn contains(&self, pos: Point, margin: f32) -> bool {
+        self.x - margin <= pos.x
+            && pos.x < self.x + self.width + margin
+            && self.y - margin <= pos.y
+            && pos.y < self.y + self.height + margin
+    }
+
     pub fn connectors(&self) -> Vec<usize> {
-        let mut ret = vec![];
-        if let Some(v) = self.left_con {
-            ret.push(v);
+        self.cons.iter().map(|(_, id)| *id).collect()
+    }
+
+    /// Connectors on the given sides, in the same order. An empty slice means any side.
+    pub fn side_connectors(&self, sides: &[Side]) -> Vec<usize> {
+        if sides.is_empty() {
+            return self.connectors();
         }
-        self.right_con.map(|v| ret.push(v));
-        self.top_con.map(|v| ret.push(v));
-        self.bottom_con.map(|v| ret.push(v));
-        ret
+        sides
+            .iter()
+            .flat_map(|side| {
+                self.cons
+                    .iter()
+                    .filter(move |(s, _)| s == side)
+                    .map(|(_, id)| *id)
+            })
+            .collect()
     }
 }
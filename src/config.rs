@@ -0,0 +1,129 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Smallest difference kept between `collision_margin`, `port_offset` and `grid_margin`
+const MARGIN_GAP: f32 = 1.;
+
+/// Strategy to order the nodes to expand in the search.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum SearchAlgorithm {
+    /// Expand by accumulated cost only, spreading out evenly in every direction.
+    Dijkstra,
+    /// Add the Manhattan distance to the nearest goal connector, so the search heads
+    /// towards the goal and expands far fewer nodes. It finds routes as good as Dijkstra's
+    /// because the distance never overestimates the remaining cost.
+    #[default]
+    AStar,
+}
+
+/// How a whole diagram is routed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum RoutingStrategy {
+    /// Route connections one after another, each avoiding the ones routed before it. The
+    /// result depends on the order of the connections.
+    #[default]
+    Sequential,
+    /// After the sequential pass, repeatedly rip up and reroute every connection against
+    /// all the others, raising the cost of grid edges that stay shared (PathFinder-style
+    /// negotiated congestion), and keep the pass with the fewest shared edges. Slower, and
+    /// usually shares fewer edges than the sequential strategy, but the result still
+    /// depends on the connection order.
+    Negotiated,
+}
+
+/// Tunable parameters of grid building and the router.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct RouterConfig {
+    /// Grid nodes closer than this to a rect are treated as inside it.
+    pub collision_margin: f32,
+    /// Distance of the connectors from the rect sides. It has to be larger than
+    /// `collision_margin`, or the connectors are blocked by their own rect; smaller values
+    /// are raised to [`Self::min_port_offset`].
+    pub port_offset: f32,
+    /// Distance from each rect edge to the grid line running along it. It has to be larger
+    /// than `port_offset` so connectors lie between the edge line and the centre line;
+    /// smaller values are raised to [`Self::min_grid_margin`].
+    pub grid_margin: f32,
+    /// Distance from the bounding box of all rects to the outermost grid lines.
+    pub grid_padding: f32,
+    /// How far a connector may be from a grid line and still be inserted on it.
+    pub intersection_tolerance: f32,
+    /// Extra cost of each corner in a route, so that among routes of similar length the
+    /// one with fewer bends wins.
+    pub bend_penalty: f32,
+    /// Extra cost of crossing a route that was routed earlier.
+    pub crossing_penalty: f32,
+    /// Extra cost of each grid edge shared with a route that was routed earlier. It should
+    /// be higher than `crossing_penalty`, since overlapping routes are harder to tell apart.
+    pub overlap_penalty: f32,
+    /// Extra cost of leaving or entering a rect through a side one step further down a
+    /// connection's list of preferred sides.
+    pub side_penalty: f32,
+    pub strategy: RoutingStrategy,
+    /// Maximum number of rip-up and reroute passes of the negotiated strategy.
+    pub negotiation_iterations: usize,
+    /// Cost added to a shared grid edge, per extra route on it, after each negotiation pass.
+    pub history_cost: f32,
+    /// Minimum length of the first and last segment of a route, measured from the side of
+    /// the rect, so that routes leave and enter rects straight instead of running along
+    /// their sides.
+    pub min_stub_length: f32,
+    /// Number of expanded nodes per grid node after which the search gives up, so that the
+    /// limit grows with the diagram.
+    pub iteration_limit: usize,
+    pub algorithm: SearchAlgorithm,
+    /// Distance between connectors that share a grid line after nudging. Zero disables
+    /// nudging.
+    pub nudge_spacing: f32,
+}
+
+impl Default for RouterConfig {
+    fn default() -> Self {
+        Self {
+            collision_margin: 2.,
+            port_offset: 4.,
+            grid_margin: 10.,
+            grid_padding: 40.,
+            intersection_tolerance: 1.,
+            bend_penalty: 30.,
+            crossing_penalty: 50.,
+            overlap_penalty: 100.,
+            side_penalty: 50.,
+            strategy: RoutingStrategy::default(),
+            negotiation_iterations: 10,
+            history_cost: 20.,
+            min_stub_length: 10.,
+            iteration_limit: 4,
+            algorithm: SearchAlgorithm::default(),
+            nudge_spacing: 6.,
+        }
+    }
+}
+
+impl RouterConfig {
+    /// Smallest `port_offset` that keeps the connectors clear of their own rect.
+    pub fn min_port_offset(&self) -> f32 {
+        self.collision_margin.max(0.) + MARGIN_GAP
+    }
+
+    /// Smallest `grid_margin` that keeps the connectors off the grid lines along the rects.
+    pub fn min_grid_margin(&self) -> f32 {
+        self.port_offset.max(self.min_port_offset()) + MARGIN_GAP
+    }
+
+    /// This config with the margins raised where needed so that `grid_margin`,
+    /// `port_offset` and `collision_margin` are in decreasing order. The grid and the
+    /// router use the margins of the clamped config.
+    pub fn clamped(mut self) -> Self {
+        self.collision_margin = self.collision_margin.max(0.);
+        self.port_offset = self.port_offset.max(self.min_port_offset());
+        self.grid_margin = self.grid_margin.max(self.min_grid_margin());
+        self
+    }
+}
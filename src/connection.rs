@@ -0,0 +1,68 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{style::ConnectionStyle, Side};
+
+/// A link between two [`ConRect`](crate::ConRect)s, referred to by their
+/// index in the rect list.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Connection {
+    pub from: usize,
+    pub to: usize,
+    /// Sides of the `from` rect the route may leave from, most preferred first. Each step
+    /// down the list costs [`RouterConfig::side_penalty`](crate::RouterConfig::side_penalty).
+    /// Empty allows every side at no extra cost.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub from_sides: Vec<Side>,
+    /// Sides of the `to` rect the route may enter from, like `from_sides`.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub to_sides: Vec<Side>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "ConnectionStyle::is_default")
+    )]
+    pub style: ConnectionStyle,
+    /// Text drawn beside the route, placed by [`place_labels`](crate::label::place_labels)
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub label: Option<String>,
+}
+
+impl Connection {
+    pub fn new(from: usize, to: usize) -> Self {
+        Self {
+            from,
+            to,
+            from_sides: vec![],
+            to_sides: vec![],
+            style: ConnectionStyle::default(),
+            label: None,
+        }
+    }
+
+    /// Restrict the sides the route leaves and enters from, most preferred first.
+    pub fn with_sides(mut self, from_sides: Vec<Side>, to_sides: Vec<Side>) -> Self {
+        self.from_sides = from_sides;
+        self.to_sides = to_sides;
+        self
+    }
+
+    pub fn with_style(mut self, style: ConnectionStyle) -> Self {
+        self.style = style;
+        self
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+}
@@ -0,0 +1,165 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    geom::{count_crossings, Point},
+    label::{place_labels, LabelPlacement},
+    nudge::nudge_routes,
+    svg, ConRect, Connection, Grid, Route, RouteError, Router, RouterConfig,
+};
+
+/// A set of rects, the connections between them and the settings to route them with.
+///
+/// With the `serde` feature this is the document saved to a diagram file. In JSON it looks
+/// like this, where `connections` and `router` may be omitted:
+///
+/// ```json
+/// {
+///   "rects": [
+///     { "x": 130.0, "y": 70.0, "width": 140.0, "height": 50.0 },
+///     { "x": 420.0, "y": 120.0, "width": 90.0, "height": 30.0 }
+///   ],
+///   "connections": [{ "from": 0, "to": 1 }],
+///   "router": { "bend_penalty": 30.0 }
+/// }
+/// ```
+///
+/// Connections refer to rects by their index in `rects`.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Diagram {
+    pub rects: Vec<ConRect>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub connections: Vec<Connection>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub router: RouterConfig,
+}
+
+impl Diagram {
+    /// Build the grid and route every connection.
+    pub fn route(&self) -> RoutedDiagram {
+        let mut rects = self.rects.clone();
+        let grid = Grid::new(&mut rects, &self.router);
+        let routes = Router::new(&grid, &rects, self.router).route_all(&self.connections);
+        RoutedDiagram {
+            rects,
+            connections: self.connections.clone(),
+            grid,
+            routes,
+            config: self.router,
+        }
+    }
+}
+
+/// A [`Diagram`] after routing, with a result for each of its connections in order.
+pub struct RoutedDiagram {
+    /// Rects with their connectors assigned by the grid.
+    pub rects: Vec<ConRect>,
+    pub connections: Vec<Connection>,
+    pub grid: Grid,
+    pub routes: Vec<Result<Route, RouteError>>,
+    pub config: RouterConfig,
+}
+
+impl RoutedDiagram {
+    /// Nudged polyline of each connection, `None` if it failed to route.
+    pub fn polylines(&self) -> Vec<Option<Vec<Point>>> {
+        nudge_routes(&self.routes, &self.rects, &self.config)
+    }
+
+    /// Number of places where the nudged connectors cross each other.
+    pub fn crossings(&self) -> usize {
+        let polylines: Vec<_> = self.polylines().into_iter().flatten().collect();
+        count_crossings(&polylines)
+    }
+
+    /// Where the label of each connection goes, `None` if it has no label or failed to
+    /// route.
+    pub fn labels(&self) -> Vec<Option<LabelPlacement>> {
+        place_labels(&self.rects, &self.connections, &self.polylines())
+    }
+
+    pub fn to_svg(&self) -> String {
+        svg::to_svg(&self.rects, &self.connections, &self.polylines())
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    use crate::{
+        style::{ConnectionStyle, LabelPosition, LinePattern, LineShape, Marker},
+        PortLayout, RoutingStrategy, SearchAlgorithm, Side,
+    };
+
+    #[test]
+    fn json_round_trip() {
+        let mut rect = ConRect::new(130., 70., 140., 50.);
+        rect.right_ports = PortLayout::Even(3);
+        rect.top_ports = PortLayout::Offsets(vec![0.25, 0.75]);
+        let style = ConnectionStyle {
+            shape: LineShape::Rounded { radius: 8. },
+            pattern: LinePattern::Dashed,
+            start_marker: Marker::Diamond,
+            end_marker: Marker::Arrow,
+            label_position: LabelPosition::End,
+        };
+        let diagram = Diagram {
+            rects: vec![rect, ConRect::new(420., 120., 90., 30.)],
+            connections: vec![
+                Connection::new(0, 1),
+                Connection::new(1, 0)
+                    .with_sides(vec![Side::Left, Side::Top], vec![Side::Right])
+                    .with_style(style)
+                    .with_label("uses"),
+            ],
+            router: RouterConfig {
+                bend_penalty: 12.,
+                strategy: RoutingStrategy::Negotiated,
+                algorithm: SearchAlgorithm::Dijkstra,
+                ..RouterConfig::default()
+            },
+        };
+
+        let json = serde_json::to_string(&diagram).unwrap();
+        let parsed: Diagram = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), json);
+
+        let rect = &parsed.rects[0];
+        assert_eq!(
+            (rect.x, rect.y, rect.width, rect.height),
+            (130., 70., 140., 50.)
+        );
+        assert_eq!(rect.right_ports, PortLayout::Even(3));
+        assert_eq!(rect.top_ports, PortLayout::Offsets(vec![0.25, 0.75]));
+        assert_eq!(rect.left_ports, PortLayout::default());
+        let connection = &parsed.connections[1];
+        assert_eq!((connection.from, connection.to), (1, 0));
+        assert_eq!(connection.from_sides, [Side::Left, Side::Top]);
+        assert_eq!(connection.to_sides, [Side::Right]);
+        assert_eq!(connection.style, style);
+        assert_eq!(connection.label.as_deref(), Some("uses"));
+        assert!(parsed.connections[0].style.is_default());
+        assert_eq!(parsed.router, diagram.router);
+    }
+
+    #[test]
+    fn omitted_fields_take_defaults() {
+        let json = r#"{
+            "rects": [
+                { "x": 130.0, "y": 70.0, "width": 140.0, "height": 50.0 },
+                { "x": 420.0, "y": 120.0, "width": 90.0, "height": 30.0 }
+            ],
+            "connections": [{ "from": 0, "to": 1 }],
+            "router": { "bend_penalty": 30.0 }
+        }"#;
+        let diagram: Diagram = serde_json::from_str(json).unwrap();
+        assert_eq!(diagram.rects[1].bottom_ports, PortLayout::default());
+        assert!(diagram.connections[0].from_sides.is_empty());
+        assert!(diagram.connections[0].label.is_none());
+        assert_eq!(diagram.router, RouterConfig::default());
+        // Defaults are left out again when saving.
+        let saved = serde_json::to_string(&diagram.connections[0]).unwrap();
+        assert_eq!(saved, r#"{"from":0,"to":1}"#);
+    }
+}
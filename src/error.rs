@@ -0,0 +1,206 @@
+use std::fmt;
+
+/// Reason a connection could not be routed. Connections and rects are referred to by their
+/// index in the diagram.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RouteError {
+    /// The connection refers to a rect that does not exist.
+    UnknownRect { connection: usize, rect: usize },
+    /// The connection goes from a rect to itself, which the router cannot route.
+    SelfLoop { connection: usize, rect: usize },
+    /// The rect has no connector on the routing grid.
+    EndpointOutsideGrid { connection: usize, rect: usize },
+    /// The rect has no ports on any of the sides the connection may use.
+    NoPortsOnSides { connection: usize, rect: usize },
+    /// Every connector of the rect is covered by another rect.
+    EndpointInsideObstacle {
+        connection: usize,
+        rect: usize,
+        obstacle: usize,
+    },
+    /// The search gave up before reaching the target.
+    IterationLimit { connection: usize, limit: usize },
+    /// Every route between the rects is blocked by other rects.
+    NoPath {
+        connection: usize,
+        from: usize,
+        to: usize,
+    },
+}
+
+impl RouteError {
+    /// Index of the connection that failed.
+    pub fn connection(&self) -> usize {
+        match *self {
+            Self::UnknownRect { connection, .. }
+            | Self::SelfLoop { connection, .. }
+            | Self::EndpointOutsideGrid { connection, .. }
+            | Self::NoPortsOnSides { connection, .. }
+            | Self::EndpointInsideObstacle { connection, .. }
+            | Self::IterationLimit { connection, .. }
+            | Self::NoPath { connection, .. } => connection,
+        }
+    }
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownRect { connection, rect } => {
+                write!(f, "Connection {connection}: rect {rect} does not exist")
+            }
+            Self::SelfLoop { connection, rect } => write!(
+                f,
+                "Connection {connection}: goes from rect {rect} to itself"
+            ),
+            Self::EndpointOutsideGrid { connection, rect } => write!(
+                f,
+                "Connection {connection}: rect {rect} has no connector on the routing grid"
+            ),
+            Self::NoPortsOnSides { connection, rect } => write!(
+                f,
+                "Connection {connection}: rect {rect} has no ports on the sides the connection may use"
+            ),
+            Self::EndpointInsideObstacle {
+                connection,
+                rect,
+                obstacle,
+            } => write!(
+                f,
+                "Connection {connection}: connectors of rect {rect} are covered by rect {obstacle}; move them apart"
+            ),
+            Self::IterationLimit { connection, limit } => write!(
+                f,
+                "Connection {connection}: search exceeded {limit} iterations"
+            ),
+            Self::NoPath {
+                connection,
+                from,
+                to,
+            } => write!(
+                f,
+                "Connection {connection}: no path from rect {from} to rect {to}; other rects block every route"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RouteError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ConRect, Connection, Grid, PortLayout, Router, RouterConfig, Side};
+
+    /// Route `connections` between `rects` and return the error of the last one.
+    fn last_error(
+        mut rects: Vec<ConRect>,
+        connections: &[Connection],
+        config: RouterConfig,
+    ) -> RouteError {
+        let grid = Grid::new(&mut rects, &config);
+        let routes = Router::new(&grid, &rects, config).route_all(connections);
+        routes.last().unwrap().clone().unwrap_err()
+    }
+
+    fn two_rects() -> Vec<ConRect> {
+        vec![
+            ConRect::new(0., 0., 60., 40.),
+            ConRect::new(200., 0., 60., 40.),
+        ]
+    }
+
+    #[test]
+    fn unknown_rect() {
+        let connections = [Connection::new(0, 1), Connection::new(1, 5)];
+        assert_eq!(
+            last_error(two_rects(), &connections, RouterConfig::default()),
+            RouteError::UnknownRect {
+                connection: 1,
+                rect: 5
+            }
+        );
+    }
+
+    #[test]
+    fn self_loop() {
+        let connections = [Connection::new(0, 1), Connection::new(1, 1)];
+        assert_eq!(
+            last_error(two_rects(), &connections, RouterConfig::default()),
+            RouteError::SelfLoop {
+                connection: 1,
+                rect: 1
+            }
+        );
+    }
+
+    #[test]
+    fn no_ports_on_sides() {
+        let mut rects = two_rects();
+        rects[0].right_ports = PortLayout::Even(0);
+        let connections = [
+            Connection::new(1, 0),
+            Connection::new(0, 1).with_sides(vec![Side::Right], vec![]),
+        ];
+        assert_eq!(
+            last_error(rects, &connections, RouterConfig::default()),
+            RouteError::NoPortsOnSides {
+                connection: 1,
+                rect: 0
+            }
+        );
+    }
+
+    #[test]
+    fn endpoint_inside_obstacle() {
+        let mut rects = two_rects();
+        rects.push(ConRect::new(220., 10., 20., 20.));
+        let connections = [Connection::new(0, 1), Connection::new(0, 2)];
+        assert_eq!(
+            last_error(rects, &connections, RouterConfig::default()),
+            RouteError::EndpointInsideObstacle {
+                connection: 1,
+                rect: 2,
+                obstacle: 1
+            }
+        );
+    }
+
+    #[test]
+    fn iteration_limit() {
+        let connections = [Connection::new(1, 0), Connection::new(0, 1)];
+        let config = RouterConfig {
+            iteration_limit: 0,
+            ..RouterConfig::default()
+        };
+        assert_eq!(
+            last_error(two_rects(), &connections, config),
+            RouteError::IterationLimit {
+                connection: 1,
+                limit: 0
+            }
+        );
+    }
+
+    #[test]
+    fn no_path() {
+        // Rect 4 is walled in by the overlapping rects 0 to 3.
+        let rects = vec![
+            ConRect::new(-20., -20., 240., 20.),
+            ConRect::new(-20., 200., 240., 20.),
+            ConRect::new(-20., -20., 20., 240.),
+            ConRect::new(200., -20., 20., 240.),
+            ConRect::new(80., 80., 40., 40.),
+            ConRect::new(400., 80., 40., 40.),
+        ];
+        let connections = [Connection::new(0, 5), Connection::new(5, 4)];
+        assert_eq!(
+            last_error(rects, &connections, RouterConfig::default()),
+            RouteError::NoPath {
+                connection: 1,
+                from: 5,
+                to: 4
+            }
+        );
+    }
+}
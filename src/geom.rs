@@ -0,0 +1,125 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A point in diagram coordinates.
+///
+/// The router does not depend on any GUI toolkit, so it has its own minimal geometry type
+/// instead of egui's `Pos2`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Point {
+    pub const fn new(x: f32, y: f32) -> Self {
+        Self { x, y }
+    }
+
+    pub fn distance_sq(self, other: Self) -> f32 {
+        (self.x - other.x).powi(2) + (self.y - other.y).powi(2)
+    }
+
+    pub fn distance(self, other: Self) -> f32 {
+        self.distance_sq(other).sqrt()
+    }
+}
+
+/// Orientation of an axis-aligned segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Axis {
+    Horizontal,
+    Vertical,
+}
+
+impl Axis {
+    /// The axis of the segment from `a` to `b`, which is assumed to be axis-aligned.
+    pub fn between(a: Point, b: Point) -> Self {
+        if (b.y - a.y).abs() <= (b.x - a.x).abs() {
+            Self::Horizontal
+        } else {
+            Self::Vertical
+        }
+    }
+
+    pub fn perpendicular(self) -> Self {
+        match self {
+            Self::Horizontal => Self::Vertical,
+            Self::Vertical => Self::Horizontal,
+        }
+    }
+}
+
+/// Remove repeated points and points in the middle of straight runs, leaving only the
+/// endpoints and bends of an axis-aligned polyline.
+pub fn simplify(polyline: &[Point]) -> Vec<Point> {
+    let mut ret: Vec<Point> = vec![];
+    for &pos in polyline {
+        if ret.last() == Some(&pos) {
+            continue;
+        }
+        if let [.., a, b] = ret[..] {
+            if (a.x == b.x && b.x == pos.x) || (a.y == b.y && b.y == pos.y) {
+                ret.pop();
+            }
+        }
+        ret.push(pos);
+    }
+    ret
+}
+
+/// Number of places where segments of different axis-aligned polylines cross each other.
+/// Segments that only touch or run along each other are not counted.
+pub fn count_crossings(polylines: &[Vec<Point>]) -> usize {
+    let mut count = 0;
+    for (i, a) in polylines.iter().enumerate() {
+        for b in &polylines[i + 1..] {
+            for sa in a.windows(2) {
+                for sb in b.windows(2) {
+                    if segments_cross(sa[0], sa[1], sb[0], sb[1]) {
+                        count += 1;
+                    }
+                }
+            }
+        }
+    }
+    count
+}
+
+fn segments_cross(a0: Point, a1: Point, b0: Point, b1: Point) -> bool {
+    let (h0, h1, v0, v1) = match (Axis::between(a0, a1), Axis::between(b0, b1)) {
+        (Axis::Horizontal, Axis::Vertical) => (a0, a1, b0, b1),
+        (Axis::Vertical, Axis::Horizontal) => (b0, b1, a0, a1),
+        _ => return false,
+    };
+    h0.x.min(h1.x) < v0.x && v0.x < h0.x.max(h1.x) && v0.y.min(v1.y) < h0.y && h0.y < v0.y.max(v1.y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simplify_keeps_only_ends_and_bends() {
+        let polyline = [
+            Point::new(0., 0.),
+            Point::new(5., 0.),
+            Point::new(5., 0.),
+            Point::new(10., 0.),
+            Point::new(10., 5.),
+            Point::new(10., 10.),
+            Point::new(20., 10.),
+        ];
+        assert_eq!(
+            simplify(&polyline),
+            [
+                Point::new(0., 0.),
+                Point::new(10., 0.),
+                Point::new(10., 10.),
+                Point::new(20., 10.),
+            ]
+        );
+    }
+}
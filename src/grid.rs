@@ -1,40 +1,108 @@
-use eframe::egui::{pos2, Pos2};
-
-use crate::{search::COLLISION_MARGIN, ConRect};
+use crate::{geom::Point, ConRect, RouterConfig, Side};
 
 #[derive(Debug, Clone)]
-pub(crate) struct GridPoint {
-    pub pos: Pos2,
+pub struct GridPoint {
+    pub pos: Point,
     pub connect: Vec<usize>,
 }
 
 impl GridPoint {
-    pub fn new(pos: Pos2, connect: Vec<usize>) -> Self {
+    pub fn new(pos: Point, connect: Vec<usize>) -> Self {
         Self { pos, connect }
     }
 }
 
-pub(crate) struct Grid {
+/// Routing graph of horizontal and vertical lines, with a node at every crossing and at
+/// every connector of the rects it was built from.
+pub struct Grid {
     pub intervals_x: Vec<f32>,
     pub intervals_y: Vec<f32>,
     pub points: Vec<GridPoint>,
 }
 
 impl Grid {
-    pub(super) fn new(con_rects: &mut [ConRect]) -> Self {
-        let mut intervals_x: Vec<_> = (0..10).map(|x| (x * 100) as f32).collect();
-        let mut intervals_y: Vec<_> = (0..10).map(|x| (x * 100) as f32).collect();
+    /// Build the grid around `con_rects` and assign each rect its connector nodes.
+    pub fn new(con_rects: &mut [ConRect], config: &RouterConfig) -> Self {
+        let config = &config.clamped();
+        let margin = config.grid_margin;
+        let padding = config.grid_padding;
+
+        let mut intervals_x = vec![];
+        let mut intervals_y = vec![];
 
-        for rect in &mut *con_rects {
-            // insert_interval(&mut intervals_x, rect.x);
+        // Rulers along the margin-offset edges and through the centre of each rect
+        for rect in &*con_rects {
+            insert_interval(&mut intervals_x, rect.x - margin);
             insert_interval(&mut intervals_x, rect.x + rect.width / 2.);
-            // insert_interval(&mut intervals_x, rect.x + rect.width);
+            insert_interval(&mut intervals_x, rect.x + rect.width + margin);
 
-            // insert_interval(&mut intervals_y, rect.y);
+            insert_interval(&mut intervals_y, rect.y - margin);
             insert_interval(&mut intervals_y, rect.y + rect.height / 2.);
-            // insert_interval(&mut intervals_y, rect.y + rect.height);
+            insert_interval(&mut intervals_y, rect.y + rect.height + margin);
+
+            // Rulers through each port, so it can be connected straight out of its side
+            for side in Side::ALL {
+                for pos in rect.port_positions(side, 0.) {
+                    match side {
+                        Side::Left | Side::Right => insert_interval(&mut intervals_y, pos.y),
+                        Side::Top | Side::Bottom => insert_interval(&mut intervals_x, pos.x),
+                    }
+                }
+            }
         }
 
+        // Rulers through the middle of the gap between each rect and its nearest neighbour
+        // on every side, so that routes run through the middle of channels. Only rects
+        // level with this one, including the margin, form a channel with it.
+        for rect in &*con_rects {
+            let (right, bottom) = (rect.x + rect.width, rect.y + rect.height);
+            let level_y = || {
+                con_rects.iter().filter(move |other| {
+                    spans_overlap(
+                        other.y,
+                        other.y + other.height,
+                        rect.y - margin,
+                        bottom + margin,
+                    )
+                })
+            };
+            let level_x = || {
+                con_rects.iter().filter(move |other| {
+                    spans_overlap(
+                        other.x,
+                        other.x + other.width,
+                        rect.x - margin,
+                        right + margin,
+                    )
+                })
+            };
+            if let Some(next) = nearest_after(level_y().map(|other| other.x), right) {
+                insert_interval(&mut intervals_x, (right + next) / 2.);
+            }
+            if let Some(prev) = nearest_before(level_y().map(|other| other.x + other.width), rect.x)
+            {
+                insert_interval(&mut intervals_x, (prev + rect.x) / 2.);
+            }
+            if let Some(next) = nearest_after(level_x().map(|other| other.y), bottom) {
+                insert_interval(&mut intervals_y, (bottom + next) / 2.);
+            }
+            if let Some(prev) =
+                nearest_before(level_x().map(|other| other.y + other.height), rect.y)
+            {
+                insert_interval(&mut intervals_y, (prev + rect.y) / 2.);
+            }
+        }
+
+        // Rulers around the bounding box of the whole diagram, so that routes can go
+        // around the outermost rects wherever the diagram is placed.
+        if let Some((min, max)) = bounding_box(con_rects) {
+            insert_interval(&mut intervals_x, min.x - padding);
+            insert_interval(&mut intervals_x, max.x + padding);
+            insert_interval(&mut intervals_y, min.y - padding);
+            insert_interval(&mut intervals_y, max.y + padding);
+        }
+
+        let x_len = intervals_x.len();
         let y_len = intervals_y.len();
 
         let borrow_intervals_x = &intervals_x;
@@ -42,43 +110,52 @@ impl Grid {
         let mut points: Vec<_> = intervals_y
             .iter()
             .enumerate()
-            .map(|(iy, y)| {
+            .flat_map(|(iy, y)| {
                 let y = *y;
                 borrow_intervals_x.iter().enumerate().map(move |(ix, x)| {
                     let mut connect = vec![];
                     if 0 < ix {
-                        connect.push(ix - 1 + iy * y_len);
+                        connect.push(ix - 1 + iy * x_len);
                     }
-                    if ix < borrow_intervals_x.len() - 1 {
-                        connect.push(ix + 1 + iy * y_len);
+                    if ix < x_len - 1 {
+                        connect.push(ix + 1 + iy * x_len);
                     }
                     if 0 < iy {
-                        connect.push(ix + (iy - 1) * y_len);
+                        connect.push(ix + (iy - 1) * x_len);
                     }
                     if iy < y_len - 1 {
-                        connect.push(ix + (iy + 1) * y_len);
+                        connect.push(ix + (iy + 1) * x_len);
                     }
-                    GridPoint::new(pos2(*x, y), connect)
+                    GridPoint::new(Point::new(*x, y), connect)
                 })
             })
-            .flatten()
             .collect();
 
+        let offset = config.port_offset;
+        let tolerance = config.intersection_tolerance;
         for rect in con_rects {
-            let pos = pos2(rect.x - COLLISION_MARGIN * 2., rect.y + rect.height / 2.);
-            rect.left_con = insert_horz_intersection(&mut points, pos);
-            let pos = pos2(
-                rect.x + rect.width + COLLISION_MARGIN * 2.,
-                rect.y + rect.height / 2.,
-            );
-            rect.right_con = insert_horz_intersection(&mut points, pos);
-            let pos = pos2(rect.x + rect.width / 2., rect.y - COLLISION_MARGIN * 2.);
-            rect.top_con = insert_vert_intersection(&mut points, pos);
-            let pos = pos2(
-                rect.x + rect.width / 2.,
-                rect.y + rect.height + COLLISION_MARGIN * 2.,
-            );
-            rect.bottom_con = insert_vert_intersection(&mut points, pos);
+            rect.cons.clear();
+            for side in Side::ALL {
+                let mut positions = rect.port_positions(side, offset);
+                positions.sort_by(|a, b| {
+                    (a.x + a.y)
+                        .partial_cmp(&(b.x + b.y))
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                });
+                for pos in positions {
+                    let con = match side {
+                        Side::Left | Side::Right => {
+                            insert_horz_intersection(&mut points, pos, tolerance)
+                        }
+                        Side::Top | Side::Bottom => {
+                            insert_vert_intersection(&mut points, pos, tolerance)
+                        }
+                    };
+                    if let Some(id) = con {
+                        rect.cons.push((side, id));
+                    }
+                }
+            }
         }
 
         Self {
@@ -87,6 +164,13 @@ impl Grid {
             points,
         }
     }
+
+    /// Convert a list of node ids into their positions, skipping unknown ids.
+    pub fn polyline(&self, path: &[usize]) -> Vec<Point> {
+        path.iter()
+            .filter_map(|i| Some(self.points.get(*i)?.pos))
+            .collect()
+    }
 }
 
 fn insert_interval(intervals: &mut Vec<f32>, pos: f32) {
@@ -94,35 +178,77 @@ fn insert_interval(intervals: &mut Vec<f32>, pos: f32) {
     let res = intervals
         .iter()
         .enumerate()
-        .find(|(_, x)| pos < **x)
+        .find(|(_, x)| pos <= **x)
         .map(|(i, _)| i);
     if let Some(res) = res {
         if pos != intervals[res] {
             intervals.insert(res, pos);
         }
+    } else {
+        intervals.push(pos);
     }
 }
 
+/// Minimum and maximum corners of the box enclosing all `con_rects`.
+pub fn bounding_box(con_rects: &[ConRect]) -> Option<(Point, Point)> {
+    let first = con_rects.first()?;
+    let init = (
+        Point::new(first.x, first.y),
+        Point::new(first.x + first.width, first.y + first.height),
+    );
+    Some(con_rects.iter().fold(init, |(min, max), rect| {
+        (
+            Point::new(min.x.min(rect.x), min.y.min(rect.y)),
+            Point::new(
+                max.x.max(rect.x + rect.width),
+                max.y.max(rect.y + rect.height),
+            ),
+        )
+    }))
+}
+
+/// Smallest value in `values` that is strictly greater than `pos`.
+fn nearest_after(values: impl Iterator<Item = f32>, pos: f32) -> Option<f32> {
+    values
+        .filter(|v| pos < *v)
+        .min_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+}
+
+fn nearest_before(values: impl Iterator<Item = f32>, pos: f32) -> Option<f32> {
+    values
+        .filter(|v| *v < pos)
+        .max_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+}
+
+/// Returns true if the spans from `a_min` to `a_max` and from `b_min` to `b_max` overlap.
+fn spans_overlap(a_min: f32, a_max: f32, b_min: f32, b_max: f32) -> bool {
+    a_min < b_max && b_min < a_max
+}
+
 struct ConIdx {
     node_id: usize,
     con_idx: usize,
 }
 
+/// The grid edge along the scan axis that passes nearest to `pos` within `tolerance`, with
+/// `pos` strictly between its ends.
 fn find_intersection(
     points: &[GridPoint],
-    pos: Pos2,
-    scan_axis: impl Fn(Pos2) -> f32,
-    fixed_axis: impl Fn(Pos2) -> f32,
+    pos: Point,
+    tolerance: f32,
+    scan_axis: impl Fn(Point) -> f32,
+    fixed_axis: impl Fn(Point) -> f32,
 ) -> Option<(ConIdx, ConIdx)> {
+    let mut nearest: Option<(f32, (usize, usize))> = None;
     for (i, point) in points.iter().enumerate() {
-        if 1. < (fixed_axis(pos) - fixed_axis(point.pos)).abs() {
+        if tolerance < (fixed_axis(pos) - fixed_axis(point.pos)).abs() {
             continue;
         }
         let pos_scan = scan_axis(pos);
         let point_scan = scan_axis(point.pos);
         for (j, con) in point.connect.iter().enumerate() {
             let point2 = &points[*con];
-            if 1. < (fixed_axis(pos) - fixed_axis(point2.pos)).abs() {
+            if tolerance < (fixed_axis(pos) - fixed_axis(point2.pos)).abs() {
                 continue;
             }
             let point2_scan = scan_axis(point2.pos);
@@ -132,36 +258,43 @@ fn find_intersection(
                 point_scan < pos_scan && pos_scan < point2_scan
             };
 
-            if intersecting {
-                return Some((
-                    ConIdx {
-                        node_id: i,
-                        con_idx: j,
-                    },
-                    ConIdx {
-                        node_id: *con,
-                        con_idx: point2
-                            .connect
-                            .iter()
-                            .enumerate()
-                            .find(|(_, v)| **v == i)
-                            .map(|(i, _)| i)?,
-                    },
-                ));
+            let offset = (fixed_axis(pos) - fixed_axis(point.pos)).abs();
+            if intersecting && nearest.is_none_or(|(nearest, _)| offset < nearest) {
+                nearest = Some((offset, (i, j)));
             }
         }
     }
-    None
+
+    let (_, (i, j)) = nearest?;
+    let con = points[i].connect[j];
+    Some((
+        ConIdx {
+            node_id: i,
+            con_idx: j,
+        },
+        ConIdx {
+            node_id: con,
+            con_idx: points[con].connect.iter().position(|v| *v == i)?,
+        },
+    ))
 }
 
 fn insert_intersection(
     points: &mut Vec<GridPoint>,
-    pos: Pos2,
-    scan_axis: impl Fn(Pos2) -> f32,
-    fixed_axis: impl Fn(Pos2) -> f32,
+    pos: Point,
+    tolerance: f32,
+    scan_axis: impl Fn(Point) -> f32,
+    fixed_axis: impl Fn(Point) -> f32,
 ) -> Option<usize> {
-    if let Some((from, to)) = find_intersection(points, pos, scan_axis, fixed_axis) {
-        let inserted_node = GridPoint::new(pos2(pos.x, pos.y), vec![from.node_id, to.node_id]);
+    if let Some((from, to)) = find_intersection(points, pos, tolerance, &scan_axis, fixed_axis) {
+        // Snap onto the edge, so that the grid stays axis-aligned.
+        let (start, end) = (points[from.node_id].pos, points[to.node_id].pos);
+        let t = (scan_axis(pos) - scan_axis(start)) / (scan_axis(end) - scan_axis(start));
+        let pos = Point::new(
+            start.x + (end.x - start.x) * t,
+            start.y + (end.y - start.y) * t,
+        );
+        let inserted_node = GridPoint::new(pos, vec![from.node_id, to.node_id]);
         let inserted_id = points.len();
         points.push(inserted_node);
         points[from.node_id].connect[from.con_idx] = inserted_id;
@@ -172,10 +305,43 @@ fn insert_intersection(
     }
 }
 
-fn insert_horz_intersection(points: &mut Vec<GridPoint>, pos: Pos2) -> Option<usize> {
-    insert_intersection(points, pos, |pos| pos.x, |pos| pos.y)
+fn insert_horz_intersection(
+    points: &mut Vec<GridPoint>,
+    pos: Point,
+    tolerance: f32,
+) -> Option<usize> {
+    insert_intersection(points, pos, tolerance, |pos| pos.x, |pos| pos.y)
+}
+
+fn insert_vert_intersection(
+    points: &mut Vec<GridPoint>,
+    pos: Point,
+    tolerance: f32,
+) -> Option<usize> {
+    insert_intersection(points, pos, tolerance, |pos| pos.y, |pos| pos.x)
 }
 
-fn insert_vert_intersection(points: &mut Vec<GridPoint>, pos: Pos2) -> Option<usize> {
-    insert_intersection(points, pos, |pos| pos.y, |pos| pos.x)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Connection, Router};
+
+    #[test]
+    fn rects_far_from_the_origin_get_connectors() {
+        let mut rects = vec![
+            ConRect::new(-500., 40., 80., 40.),
+            ConRect::new(2000., -300., 60., 50.),
+        ];
+        let config = RouterConfig::default();
+        let grid = Grid::new(&mut rects, &config);
+        for rect in &rects {
+            assert_eq!(rect.cons.len(), Side::ALL.len());
+        }
+
+        let connections = [Connection::new(0, 1), Connection::new(1, 0)];
+        let routes = Router::new(&grid, &rects, config).route_all(&connections);
+        for route in routes {
+            route.unwrap();
+        }
+    }
 }
@@ -0,0 +1,197 @@
+//! Placement of connection labels beside their routes.
+//!
+//! The library does not depend on a font, so label sizes are estimated from the number of
+//! characters at [`FONT_SIZE`]. Renderers should draw the text centred in the placed box at
+//! that size.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    geom::{Axis, Point},
+    style::LabelPosition,
+    ConRect, Connection,
+};
+
+/// Font size labels are placed for
+pub const FONT_SIZE: f32 = 12.;
+
+/// Estimated average width of a character relative to the font size
+const CHAR_WIDTH: f32 = 0.6;
+
+/// Distance between a label and the segment it belongs to
+const GAP: f32 = 3.;
+
+/// Distance between the candidate positions tried along a route
+const STEP: f32 = 10.;
+
+/// Distance of labels at the start or end from that end of the route, so they clear the
+/// end markers
+const END_DISTANCE: f32 = 20.;
+
+/// Number of candidate positions tried on each side of the preferred one
+const MAX_SHIFTS: usize = 10;
+
+/// Where a label is drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LabelPlacement {
+    /// Centre of the text
+    pub center: Point,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl LabelPlacement {
+    fn overlaps(&self, other: &Self) -> bool {
+        (self.center.x - other.center.x).abs() * 2. < self.width + other.width
+            && (self.center.y - other.center.y).abs() * 2. < self.height + other.height
+    }
+
+    fn overlaps_rect(&self, rect: &ConRect) -> bool {
+        let (min_x, min_y) = (
+            self.center.x - self.width / 2.,
+            self.center.y - self.height / 2.,
+        );
+        min_x < rect.x + rect.width
+            && rect.x < min_x + self.width
+            && min_y < rect.y + rect.height
+            && rect.y < min_y + self.height
+    }
+}
+
+/// Place the label of each connection beside its polyline, where it overlaps neither the
+/// rects nor the labels placed before it. If there is no such place, the label goes to
+/// the preferred one anyway. `polylines` are in the same order as `connections`, with `None`
+/// for connections that failed to route; their labels are not placed.
+pub fn place_labels(
+    con_rects: &[ConRect],
+    connections: &[Connection],
+    polylines: &[Option<Vec<Point>>],
+) -> Vec<Option<LabelPlacement>> {
+    let mut placed: Vec<LabelPlacement> = vec![];
+    connections
+        .iter()
+        .zip(polylines)
+        .map(|(connection, polyline)| {
+            let text = connection.label.as_ref()?;
+            let polyline = polyline.as_ref().filter(|p| 2 <= p.len())?;
+            let width = text.chars().count() as f32 * FONT_SIZE * CHAR_WIDTH;
+            let candidates: Vec<_> = anchors(polyline, connection.style.label_position)
+                .into_iter()
+                .flat_map(|(pos, axis)| beside(pos, axis, width, FONT_SIZE))
+                .collect();
+            let free = candidates.iter().find(|candidate| {
+                !con_rects.iter().any(|rect| candidate.overlaps_rect(rect))
+                    && !placed.iter().any(|other| candidate.overlaps(other))
+            });
+            let placement = *free.or(candidates.first())?;
+            placed.push(placement);
+            Some(placement)
+        })
+        .collect()
+}
+
+/// Points on the polyline to put a label next to, most preferred first, with the axis of
+/// the segment they are on.
+fn anchors(polyline: &[Point], position: LabelPosition) -> Vec<(Point, Axis)> {
+    let lengths: Vec<_> = polyline.windows(2).map(|w| w[0].distance(w[1])).collect();
+    let total: f32 = lengths.iter().sum();
+    // Preferred distance along the polyline, and the range to move away from it within
+    let ranges = match position {
+        LabelPosition::LongestSegment => {
+            let mut segments: Vec<_> = (0..lengths.len()).collect();
+            segments.sort_by(|a, b| lengths[*b].total_cmp(&lengths[*a]));
+            segments
+                .into_iter()
+                .map(|i| {
+                    let start: f32 = lengths[..i].iter().sum();
+                    let end = start + lengths[i];
+                    ((start + end) / 2., start, end)
+                })
+                .collect()
+        }
+        LabelPosition::Start => vec![(END_DISTANCE.min(total / 2.), 0., total)],
+        LabelPosition::Middle => vec![(total / 2., 0., total)],
+        LabelPosition::End => vec![((total - END_DISTANCE).max(total / 2.), 0., total)],
+    };
+    ranges
+        .into_iter()
+        .flat_map(|(preferred, min, max)| {
+            (0..=MAX_SHIFTS)
+                .flat_map(move |shift| {
+                    let shift = shift as f32 * STEP;
+                    [preferred - shift, preferred + shift]
+                })
+                .skip(1)
+                .filter(move |d| min <= *d && *d <= max)
+        })
+        .map(|d| point_at(polyline, &lengths, d))
+        .collect()
+}
+
+/// The point `distance` along the polyline, with the axis of its segment there.
+fn point_at(polyline: &[Point], lengths: &[f32], mut distance: f32) -> (Point, Axis) {
+    let last = lengths.len() - 1;
+    for (i, (w, length)) in polyline.windows(2).zip(lengths).enumerate() {
+        if distance <= *length || i == last {
+            let t = if 0. < *length {
+                (distance / length).min(1.)
+            } else {
+                0.
+            };
+            let pos = Point::new(
+                w[0].x + (w[1].x - w[0].x) * t,
+                w[0].y + (w[1].y - w[0].y) * t,
+            );
+            return (pos, Axis::between(w[0], w[1]));
+        }
+        distance -= length;
+    }
+    (polyline[0], Axis::Horizontal)
+}
+
+/// Boxes for a label next to `pos` on a segment along `axis`: above or to the right of it
+/// first, then below or to the left.
+fn beside(pos: Point, axis: Axis, width: f32, height: f32) -> [LabelPlacement; 2] {
+    let at = |x, y| LabelPlacement {
+        center: Point::new(x, y),
+        width,
+        height,
+    };
+    match axis {
+        Axis::Horizontal => {
+            let dy = height / 2. + GAP;
+            [at(pos.x, pos.y - dy), at(pos.x, pos.y + dy)]
+        }
+        Axis::Vertical => {
+            let dx = width / 2. + GAP;
+            [at(pos.x + dx, pos.y), at(pos.x - dx, pos.y)]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_avoid_rects() {
+        let connections = [Connection::new(0, 1).with_label("abc")];
+        let polylines = [Some(vec![Point::new(0., 0.), Point::new(200., 0.)])];
+
+        // Above the middle of the route is preferred.
+        let [Some(label)] = place_labels(&[], &connections, &polylines)[..] else {
+            panic!("label not placed");
+        };
+        assert_eq!(label.center, Point::new(100., -FONT_SIZE / 2. - GAP));
+
+        // With a rect there, the label goes below the route instead.
+        let rects = [ConRect::new(90., -30., 20., 25.)];
+        let [Some(label)] = place_labels(&rects, &connections, &polylines)[..] else {
+            panic!("label not placed");
+        };
+        assert!(!label.overlaps_rect(&rects[0]));
+        assert_eq!(label.center, Point::new(100., FONT_SIZE / 2. + GAP));
+    }
+}
@@ -0,0 +1,52 @@
+//! Routing engine for orthogonal connectors between rectangles that avoids other
+//! rectangles on the way.
+//!
+//! The engine has no GUI dependency. Build a [`Grid`] from the obstacles, then route
+//! [`Connection`]s on it with a [`Router`]:
+//!
+//! ```
+//! use box_connector::{ConRect, Connection, Grid, Router, RouterConfig};
+//!
+//! let mut con_rects = vec![
+//!     ConRect::new(130., 70., 140., 50.),
+//!     ConRect::new(420., 120., 90., 30.),
+//! ];
+//! let config = RouterConfig::default();
+//! let grid = Grid::new(&mut con_rects, &config);
+//! let router = Router::new(&grid, &con_rects, config);
+//! let route = router.route(0, &Connection::new(0, 1)).unwrap();
+//! // The grid nodes along the route, and just its ends and bends
+//! assert!(route.polyline.len() <= route.path.len());
+//! assert!(2 <= route.polyline.len());
+//! ```
+//!
+//! Connectors sharing a grid line can be spread apart with [`nudge`].
+//! [`Diagram::route`] does all of this for a whole diagram, and the result can be exported
+//! with the [`svg`] module. The [`style`] module turns polylines into the outlines drawn
+//! for each connection.
+//!
+//! With the `serde` feature, [`Diagram`] and the types in it can be saved and loaded.
+
+mod con_rect;
+mod config;
+mod connection;
+mod diagram;
+mod error;
+mod geom;
+mod grid;
+pub mod label;
+pub mod nudge;
+mod search;
+pub mod style;
+pub mod svg;
+
+pub use crate::{
+    con_rect::{ConRect, PortLayout, Side},
+    config::{RouterConfig, RoutingStrategy, SearchAlgorithm},
+    connection::Connection,
+    diagram::{Diagram, RoutedDiagram},
+    error::RouteError,
+    geom::{count_crossings, simplify, Axis, Point},
+    grid::{bounding_box, Grid, GridPoint},
+    search::{Route, Router, SearchState, VisitedMap, VisitedNode},
+};
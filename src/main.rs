@@ -1,33 +1,82 @@
-mod con_rect;
-mod grid;
-mod search;
+use std::path::Path;
 
+use box_connector::{
+    bounding_box, count_crossings, label,
+    nudge::nudge_routes,
+    style::{self, LabelPosition, LinePattern, LineShape, Marker, MarkerShape},
+    svg, ConRect, Connection, Diagram, Grid, Point, Route, Router, RouterConfig, RoutingStrategy,
+    SearchAlgorithm, Side, VisitedMap,
+};
 use eframe::{
     egui::{
-        vec2, Align2, CentralPanel, FontId, Frame, Painter, Response, RichText, Sense, Shape,
-        SidePanel, Ui,
+        vec2, Align2, CentralPanel, ComboBox, CursorIcon, FontId, Frame, Key, Painter,
+        PointerButton, Response, RichText, Sense, Shape, SidePanel, Slider, Ui,
     },
-    emath::{self, RectTransform},
-    epaint::{pos2, Color32, Pos2, Rect},
+    emath::RectTransform,
+    epaint::{pos2, Color32, Pos2, Rect, Stroke, Vec2},
 };
-use search::VisitedMap;
 
-use crate::{con_rect::ConRect, grid::Grid};
+/// Size of the boxes created in the editor
+const DEFAULT_BOX_SIZE: Vec2 = vec2(120., 60.);
+/// Smallest width and height a box can be resized to
+const MIN_BOX_SIZE: f32 = 10.;
+/// Size of the resize handles of the selected box, in screen pixels
+const HANDLE_SIZE: f32 = 8.;
+
+/// Distance of the port knobs that connections are dragged from outside the box sides,
+/// and their radius, in screen pixels
+const PORT_KNOB_DISTANCE: f32 = 14.;
+const PORT_KNOB_RADIUS: f32 = 5.;
+/// Distance from a connection within which a click selects it, in screen pixels
+const CONNECTION_HIT_DISTANCE: f32 = 5.;
+
+/// Resize handles at the corners and the middle of the edges of a box. Each component is
+/// -1 for the left or top edge, 1 for the right or bottom edge and 0 for the middle.
+const HANDLES: [Vec2; 8] = [
+    vec2(-1., -1.),
+    vec2(0., -1.),
+    vec2(1., -1.),
+    vec2(1., 0.),
+    vec2(1., 1.),
+    vec2(0., 1.),
+    vec2(-1., 1.),
+    vec2(-1., 0.),
+];
+
+/// Corner radius given to a connection when its shape is switched to rounded
+const DEFAULT_CORNER_RADIUS: f32 = 8.;
+
+/// Range of the canvas zoom, in screen pixels per diagram unit
+const MIN_ZOOM: f32 = 0.1;
+const MAX_ZOOM: f32 = 10.;
+/// Zoom factor per pixel of mouse wheel scrolling
+const WHEEL_ZOOM_SPEED: f32 = 0.002;
+/// Space around the diagram after zooming to fit, in screen pixels
+const FIT_MARGIN: f32 = 20.;
 
 fn main() {
-    let con_rects = vec![
-        ConRect::new(130., 70., 140., 50.),
-        ConRect::new(420., 120., 90., 30.),
-        ConRect::new(260., 420., 150., 60.),
-    ];
+    let file_path = std::env::args().nth(1);
 
-    let app_data = AppData::new(con_rects);
+    let diagram = if let Some(ref file_path) = file_path {
+        match load_diagram(Path::new(file_path)) {
+            Ok(diagram) => diagram,
+            Err(e) => {
+                eprintln!("Error: {e}");
+                std::process::exit(1);
+            }
+        }
+    } else {
+        default_diagram()
+    };
+
+    let app_data = AppData::new(diagram);
+    let file_path = file_path.unwrap_or_else(|| "diagram.json".to_string());
 
     let native_options = eframe::NativeOptions::default();
     eframe::run_native(
         "box-connector application in eframe",
         native_options,
-        Box::new(move |_cc| Ok(Box::new(App::new(app_data)))),
+        Box::new(move |_cc| Ok(Box::new(App::new(app_data, file_path)))),
     )
     .unwrap();
 }
@@ -41,16 +90,51 @@ pub struct App {
     show_search_graph: bool,
     auto_find_path: bool,
     error_msg: Option<String>,
+    file_path: String,
+    view: View,
+    /// Zoom to fit on the next frame, when the canvas size is known
+    fit_requested: bool,
+    /// Add a box in the middle of the view on the next frame
+    add_box_requested: bool,
+    /// What dragging with the primary button is doing to the selected rect
+    drag: Option<Drag>,
+    /// Connection being dragged out from a rect
+    new_connection: Option<NewConnection>,
+}
+
+#[derive(Clone, Copy)]
+enum Drag {
+    /// Moving it, with the pointer at `offset` from its top left corner in diagram units
+    Move { offset: Vec2 },
+    /// Resizing it with one of the [`HANDLES`]
+    Resize { handle: Vec2 },
+}
+
+/// A connection dragged from a rect, before it is dropped on another one.
+struct NewConnection {
+    from: usize,
+    from_sides: Vec<Side>,
+    /// Rect and sides under the pointer that the connection would go to
+    target: Option<(usize, Vec<Side>)>,
+    /// Route to `target`
+    preview: Option<Vec<Point>>,
 }
 
 struct AppData {
     con_rects: Vec<ConRect>,
+    connections: Vec<Connection>,
     grid: Grid,
     start_nodes: Vec<usize>,
     goal_nodes: Vec<usize>,
-    path: Option<Vec<usize>>,
+    /// Routed path for each entry in `connections`, `None` if it failed to route.
+    paths: Vec<Option<Route>>,
+    /// Nudged polylines of `paths`
+    polylines: Vec<Option<Vec<Point>>>,
+    crossings: usize,
     selected_rect: Option<usize>,
-    visited_nodes: Option<VisitedMap>,
+    selected_connection: Option<usize>,
+    visited_nodes: Vec<VisitedMap>,
+    config: RouterConfig,
 }
 
 impl eframe::App for App {
@@ -65,15 +149,60 @@ impl eframe::App for App {
                     ui.label("No errors");
                 };
                 if ui.button("Find path").clicked() {
-                    if let Err(e) = self.app_data.search() {
-                        self.error_msg = Some(e);
-                    }
+                    self.error_msg = self.app_data.search().err();
                 }
                 ui.checkbox(&mut self.auto_find_path, "Auto find path");
+                let config_changed = ui
+                    .collapsing("Router settings", |ui| {
+                        config_ui(ui, &mut self.app_data.config)
+                    })
+                    .body_returned
+                    .unwrap_or(false);
+                if config_changed {
+                    self.diagram_edited();
+                }
+                ui.collapsing("Connections", |ui| {
+                    for (i, connection) in self.app_data.connections.iter_mut().enumerate() {
+                        ui.push_id(i, |ui| connection_ui(ui, connection));
+                    }
+                });
+                if ui.button("Add box").clicked() {
+                    self.add_box_requested = true;
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Zoom to fit").clicked() {
+                        self.fit_requested = true;
+                    }
+                    ui.label(format!("{:.0}%", self.view.zoom * 100.));
+                });
                 ui.checkbox(&mut self.show_grid, "Show grid");
                 ui.checkbox(&mut self.show_grid_label, "Show grid labels");
                 ui.checkbox(&mut self.show_grid_cost, "Show grid cost");
                 ui.checkbox(&mut self.show_search_graph, "Show search graph");
+                let visited: usize = self.app_data.visited_nodes.iter().map(|v| v.len()).sum();
+                ui.label(format!("Visited search nodes: {visited}"));
+                ui.label(format!("Crossings: {}", self.app_data.crossings));
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label("File:");
+                    ui.text_edit_singleline(&mut self.file_path);
+                });
+                ui.horizontal(|ui| {
+                    if ui.button("Open").clicked() {
+                        self.open();
+                    }
+                    if ui.button("Save").clicked() {
+                        self.error_msg =
+                            save_diagram(Path::new(&self.file_path), &self.app_data.to_diagram())
+                                .err();
+                    }
+                    if ui.button("Export SVG").clicked() {
+                        let path = Path::new(&self.file_path).with_extension("svg");
+                        self.error_msg = std::fs::write(&path, self.app_data.to_svg())
+                            .map_err(|e| format!("Failed to write {}: {e}", path.display()))
+                            .err();
+                    }
+                });
             });
 
         CentralPanel::default().show(ctx, |ui| {
@@ -90,10 +219,68 @@ struct UiResult {
     hover_pos: Option<Pos2>,
     mouse_down: bool,
     mouse_up: bool,
+    double_click: bool,
+    shift: bool,
+    /// Delete key pressed while no text field has keyboard focus
+    delete: bool,
+    /// Zoom factor requested with the mouse wheel or a pinch
+    zoom: f32,
+    /// Screen distance the view is dragged by with the middle button or space and the
+    /// primary button
+    pan: Option<Vec2>,
+}
+
+/// The part of the diagram shown on the canvas.
+struct View {
+    /// Diagram position at the top left corner of the canvas
+    origin: Pos2,
+    /// Screen pixels per diagram unit
+    zoom: f32,
+}
+
+impl Default for View {
+    fn default() -> Self {
+        Self {
+            origin: Pos2::ZERO,
+            zoom: 1.,
+        }
+    }
+}
+
+impl View {
+    /// Transform from diagram to screen coordinates on `canvas`.
+    fn to_screen(&self, canvas: Rect) -> RectTransform {
+        RectTransform::from_to(
+            Rect::from_min_size(self.origin, canvas.size() / self.zoom),
+            canvas,
+        )
+    }
+
+    /// Zoom by `factor`, keeping the diagram position under `screen_pos` in place.
+    fn zoom_around(&mut self, canvas: Rect, screen_pos: Pos2, factor: f32) {
+        let anchor = self.to_screen(canvas).inverse().transform_pos(screen_pos);
+        self.zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        self.origin = anchor - (screen_pos - canvas.min) / self.zoom;
+    }
+
+    fn pan(&mut self, screen_delta: Vec2) {
+        self.origin -= screen_delta / self.zoom;
+    }
+
+    /// Show all of the diagram area from `min` to `max` in the middle of `canvas`.
+    fn fit(&mut self, canvas: Rect, min: Point, max: Point) {
+        let size = (canvas.size() - Vec2::splat(2. * FIT_MARGIN)).max(Vec2::splat(1.));
+        let (width, height) = ((max.x - min.x).max(1.), (max.y - min.y).max(1.));
+        self.zoom = (size.x / width)
+            .min(size.y / height)
+            .clamp(MIN_ZOOM, MAX_ZOOM);
+        let center = pos2((min.x + max.x) / 2., (min.y + max.y) / 2.);
+        self.origin = center - canvas.size() / 2. / self.zoom;
+    }
 }
 
 impl App {
-    fn new(app_data: AppData) -> Self {
+    fn new(app_data: AppData, file_path: String) -> Self {
         Self {
             app_data,
             show_grid: false,
@@ -102,95 +289,331 @@ impl App {
             show_search_graph: false,
             auto_find_path: true,
             error_msg: None,
+            file_path,
+            view: View::default(),
+            fit_requested: false,
+            add_box_requested: false,
+            drag: None,
+            new_connection: None,
         }
     }
 
+    fn open(&mut self) {
+        match load_diagram(Path::new(&self.file_path)) {
+            Ok(diagram) => {
+                self.app_data = AppData::new(diagram);
+                self.error_msg = if self.auto_find_path {
+                    self.app_data.search().err()
+                } else {
+                    None
+                };
+            }
+            Err(e) => self.error_msg = Some(e),
+        }
+    }
+
+    /// Rebuild the grid after the rects, connections or router settings changed, and
+    /// reroute if automatic routing is on.
+    fn diagram_edited(&mut self) {
+        self.app_data.rebuild_grid();
+        if self.auto_find_path {
+            self.error_msg = self.app_data.search().err();
+        } else {
+            self.app_data.clear_routes();
+        }
+    }
+
+    /// The resize handle of the selected rect at `screen_pos`.
+    fn handle_at(&self, to_screen: &RectTransform, screen_pos: Pos2) -> Option<Vec2> {
+        let rect = self
+            .app_data
+            .selected_rect
+            .and_then(|i| self.app_data.con_rects.get(i))?;
+        HANDLES.into_iter().find(|handle| {
+            handle_rect(to_screen.transform_pos(handle_pos(rect, *handle))).contains(screen_pos)
+        })
+    }
+
+    /// The port knob at `screen_pos`, as the rect and the side it is on.
+    fn knob_at(&self, to_screen: &RectTransform, screen_pos: Pos2) -> Option<(usize, Side)> {
+        self.app_data
+            .con_rects
+            .iter()
+            .enumerate()
+            .find_map(|(i, rect)| {
+                port_knobs(rect, to_screen)
+                    .into_iter()
+                    .find(|(_, pos)| pos.distance(screen_pos) <= PORT_KNOB_RADIUS)
+                    .map(|(side, _)| (i, side))
+            })
+    }
+
+    /// The connection closest to `screen_pos`, if it is near enough to be clicked.
+    fn connection_at(&self, to_screen: &RectTransform, screen_pos: Pos2) -> Option<usize> {
+        self.app_data
+            .connections
+            .iter()
+            .zip(&self.app_data.polylines)
+            .enumerate()
+            .filter_map(|(i, (connection, polyline))| {
+                let path_pos = screen_path(connection, polyline.as_ref()?, to_screen);
+                let distance = path_pos
+                    .windows(2)
+                    .map(|w| segment_distance(screen_pos, w[0], w[1]))
+                    .fold(f32::INFINITY, f32::min);
+                Some((i, distance))
+            })
+            .filter(|(_, distance)| *distance <= CONNECTION_HIT_DISTANCE)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i)
+    }
+
+    fn start_connection(&mut self, from: usize, from_sides: Vec<Side>) {
+        self.app_data.selected_rect = None;
+        self.app_data.selected_connection = None;
+        self.new_connection = Some(NewConnection {
+            from,
+            from_sides,
+            target: None,
+            preview: None,
+        });
+    }
+
     fn draw(&mut self, ui: &mut Ui, response: &Response, painter: &Painter) {
+        let wants_keyboard = ui.ctx().wants_keyboard_input();
         let ui_result = ui.input(|input| {
             let interact_pos = input.pointer.interact_pos();
+            let space_drag = input.key_down(Key::Space) && input.pointer.primary_down();
             UiResult {
                 interact_pos,
                 hover_pos: input.pointer.hover_pos(),
                 mouse_up: input.pointer.primary_released(),
                 mouse_down: input.pointer.primary_pressed(),
+                double_click: input.pointer.button_double_clicked(PointerButton::Primary),
+                shift: input.modifiers.shift,
+                delete: !wants_keyboard && input.key_pressed(Key::Delete),
+                zoom: input.zoom_delta() * (input.smooth_scroll_delta.y * WHEEL_ZOOM_SPEED).exp(),
+                pan: (input.pointer.middle_down() || space_drag).then(|| input.pointer.delta()),
             }
         });
 
-        let to_screen = emath::RectTransform::from_to(
-            Rect::from_min_size(Pos2::ZERO, response.rect.size()),
-            response.rect,
-        );
+        let canvas = response.rect;
+        if self.fit_requested {
+            self.fit_requested = false;
+            if let Some((min, max)) = self.app_data.extent() {
+                self.view.fit(canvas, min, max);
+            }
+        }
+        if response.hovered() {
+            if let Some(hover_pos) = ui_result.hover_pos {
+                if ui_result.zoom != 1. {
+                    self.view.zoom_around(canvas, hover_pos, ui_result.zoom);
+                }
+            }
+            if let Some(delta) = ui_result.pan {
+                self.view.pan(delta);
+            }
+        }
+        let to_screen = self.view.to_screen(canvas);
 
-        if let Some(mouse_pos) = ui_result.interact_pos {
-            if ui_result.mouse_down {
-                for (i, con_rect) in self.app_data.con_rects.iter().enumerate() {
-                    let rect_min = to_screen.transform_pos(pos2(con_rect.x, con_rect.y));
-                    let rect_max = to_screen.transform_pos(pos2(
-                        con_rect.x + con_rect.width,
-                        con_rect.y + con_rect.height,
-                    ));
-                    if rect_min.x < mouse_pos.x
-                        && mouse_pos.x < rect_max.x
-                        && rect_min.y < mouse_pos.y
-                        && mouse_pos.y < rect_max.y
-                    {
-                        self.app_data.selected_rect = Some(i);
-                        break;
-                    }
+        if self.add_box_requested {
+            self.add_box_requested = false;
+            let center = to_screen.inverse().transform_pos(canvas.center());
+            self.app_data.selected_rect = Some(self.app_data.add_rect(center));
+            self.diagram_edited();
+        }
+
+        if let Some(mouse_pos) = ui_result.interact_pos.filter(|_| ui_result.pan.is_none()) {
+            let diagram_pos = to_screen.inverse().transform_pos(mouse_pos);
+            if ui_result.mouse_down && response.hovered() {
+                let knob = self.knob_at(&to_screen, mouse_pos);
+                let rect = self.app_data.rect_at(diagram_pos);
+                if let Some(handle) = self.handle_at(&to_screen, mouse_pos) {
+                    self.drag = Some(Drag::Resize { handle });
+                } else if let Some((from, side)) = knob {
+                    self.start_connection(from, vec![side]);
+                } else if let Some(from) = rect.filter(|_| ui_result.shift) {
+                    self.start_connection(from, vec![]);
+                } else {
+                    self.app_data.selected_rect = rect;
+                    self.app_data.selected_connection = if rect.is_none() {
+                        self.connection_at(&to_screen, mouse_pos)
+                    } else {
+                        None
+                    };
+                    self.drag = rect.map(|i| {
+                        let rect = &self.app_data.con_rects[i];
+                        Drag::Move {
+                            offset: diagram_pos - pos2(rect.x, rect.y),
+                        }
+                    });
                 }
             }
 
-            let mut moved = false;
-            if let Some(selected) = self
-                .app_data
-                .selected_rect
-                .and_then(|s| self.app_data.con_rects.get_mut(s))
+            if ui_result.double_click
+                && response.hovered()
+                && self.app_data.selected_rect.is_none()
+                && self.app_data.selected_connection.is_none()
+                && self.new_connection.is_none()
             {
-                let move_pos = to_screen.inverse().transform_pos(mouse_pos)
-                    - vec2(selected.width / 2., selected.height / 2.);
-                moved = selected.x != move_pos.x || selected.y != move_pos.y;
-                selected.x = move_pos.x;
-                selected.y = move_pos.y;
+                self.app_data.selected_rect = Some(self.app_data.add_rect(diagram_pos));
+                self.diagram_edited();
             }
 
-            if moved {
-                self.app_data.grid = Grid::new(&mut self.app_data.con_rects);
-                if self.auto_find_path {
-                    if let Err(e) = self.app_data.search() {
-                        self.error_msg = Some(e);
-                    } else {
-                        self.error_msg = None;
+            let mut changed = false;
+            if let (Some(drag), Some(selected)) = (
+                self.drag,
+                self.app_data
+                    .selected_rect
+                    .and_then(|s| self.app_data.con_rects.get_mut(s)),
+            ) {
+                let before = (selected.x, selected.y, selected.width, selected.height);
+                match drag {
+                    Drag::Move { offset } => {
+                        let move_pos = diagram_pos - offset;
+                        selected.x = move_pos.x;
+                        selected.y = move_pos.y;
                     }
+                    Drag::Resize { handle } => resize(selected, handle, diagram_pos),
+                }
+                changed = before != (selected.x, selected.y, selected.width, selected.height);
+            }
+
+            if changed {
+                self.diagram_edited();
+            }
+
+            if let Some(mut new_connection) = self.new_connection.take() {
+                let target = self
+                    .knob_at(&to_screen, mouse_pos)
+                    .map(|(i, side)| (i, vec![side]))
+                    .or_else(|| self.app_data.rect_at(diagram_pos).map(|i| (i, vec![])))
+                    .filter(|(i, _)| *i != new_connection.from);
+                if target != new_connection.target {
+                    new_connection.preview = target.as_ref().and_then(|(to, to_sides)| {
+                        self.app_data.route(
+                            &Connection::new(new_connection.from, *to)
+                                .with_sides(new_connection.from_sides.clone(), to_sides.clone()),
+                        )
+                    });
+                    new_connection.target = target;
                 }
+                self.new_connection = Some(new_connection);
             }
         }
 
         if ui_result.mouse_up {
-            self.app_data.selected_rect = None;
+            self.drag = None;
+            if let Some(new_connection) = self.new_connection.take() {
+                if let Some((to, to_sides)) = new_connection.target {
+                    self.app_data.connections.push(
+                        Connection::new(new_connection.from, to)
+                            .with_sides(new_connection.from_sides, to_sides),
+                    );
+                    self.app_data.selected_connection = Some(self.app_data.connections.len() - 1);
+                    self.diagram_edited();
+                }
+            }
+        }
+
+        let cursor_handle = match self.drag {
+            Some(Drag::Resize { handle }) => Some(handle),
+            Some(Drag::Move { .. }) => None,
+            None => ui_result
+                .hover_pos
+                .and_then(|pos| self.handle_at(&to_screen, pos)),
+        };
+        if let Some(handle) = cursor_handle {
+            ui.ctx().set_cursor_icon(handle_cursor(handle));
+        }
+
+        if ui_result.delete {
+            if let Some(selected) = self.app_data.selected_connection.take() {
+                self.app_data.connections.remove(selected);
+                self.diagram_edited();
+            } else if let Some(selected) = self.app_data.selected_rect.take() {
+                self.drag = None;
+                self.new_connection = None;
+                self.app_data.remove_rect(selected);
+                self.diagram_edited();
+            }
         }
 
         if self.show_grid {
-            self.draw_grid(&ui_result, response, painter, &to_screen);
+            self.draw_grid(&ui_result, painter, &to_screen);
         }
 
-        if let Some(ref path) = self.app_data.path {
-            let path_pos: Vec<_> = path
-                .iter()
-                .filter_map(|i| {
-                    Some(to_screen.transform_pos(self.app_data.grid.points.get(*i)?.pos))
-                })
-                .collect();
-            let line = Shape::line(path_pos, (2., Color32::RED));
-            painter.add(line);
+        for (i, (connection, polyline)) in self
+            .app_data
+            .connections
+            .iter()
+            .zip(&self.app_data.polylines)
+            .enumerate()
+        {
+            let Some(polyline) = polyline else {
+                continue;
+            };
+            let path_pos = screen_path(connection, polyline, &to_screen);
+            let stroke = if self.app_data.selected_connection == Some(i) {
+                Stroke::new(3., Color32::ORANGE)
+            } else {
+                Stroke::new(2., Color32::RED)
+            };
+            match connection.style.pattern.dashes() {
+                Some((dash, gap)) => {
+                    painter.extend(Shape::dashed_line(&path_pos, stroke, dash, gap))
+                }
+                None => {
+                    painter.add(Shape::line(path_pos, stroke));
+                }
+            }
+            for marker in connection.style.markers(polyline) {
+                painter.add(marker_shape(&marker, &to_screen, stroke));
+            }
+        }
+
+        if let Some(new_connection) = &self.new_connection {
+            let stroke = Stroke::new(2., Color32::GRAY);
+            if let Some(preview) = &new_connection.preview {
+                let path_pos: Vec<_> = preview
+                    .iter()
+                    .map(|pos| to_screen.transform_pos(to_pos2(*pos)))
+                    .collect();
+                painter.extend(Shape::dashed_line(&path_pos, stroke, 8., 4.));
+            } else if let (Some(from), Some(mouse_pos)) = (
+                self.app_data.con_rects.get(new_connection.from),
+                ui_result.interact_pos,
+            ) {
+                let start = match new_connection.from_sides[..] {
+                    [side] => port_knobs(from, &to_screen)
+                        .into_iter()
+                        .find(|(knob_side, _)| *knob_side == side)
+                        .map(|(_, pos)| pos),
+                    _ => None,
+                }
+                .unwrap_or_else(|| {
+                    to_screen
+                        .transform_pos(pos2(from.x + from.width / 2., from.y + from.height / 2.))
+                });
+                painter.extend(Shape::dashed_line(&[start, mouse_pos], stroke, 8., 4.));
+            }
         }
 
+        // The rect under the pointer, and the one a new connection would go to
+        let hovered_rect = match &self.new_connection {
+            Some(new_connection) => new_connection.target.as_ref().map(|(i, _)| *i),
+            None => ui_result.hover_pos.and_then(|pos| {
+                self.app_data
+                    .rect_at(to_screen.inverse().transform_pos(pos))
+            }),
+        };
         for (i, con_rect) in self.app_data.con_rects.iter().enumerate() {
             let rect = Rect {
                 min: Pos2::new(con_rect.x, con_rect.y),
                 max: Pos2::new(con_rect.x + con_rect.width, con_rect.y + con_rect.height),
             };
 
-            let hover = false;
+            let hover = hovered_rect == Some(i);
 
             let color = if self.app_data.selected_rect == Some(i) {
                 Color32::RED
@@ -202,20 +625,64 @@ impl App {
 
             painter.rect_stroke(to_screen.transform_rect(rect), 0., (2., color));
         }
+
+        if let Some(selected) = self
+            .app_data
+            .selected_rect
+            .and_then(|i| self.app_data.con_rects.get(i))
+        {
+            for handle in HANDLES {
+                let pos = to_screen.transform_pos(handle_pos(selected, handle));
+                painter.rect(handle_rect(pos), 0., Color32::WHITE, (1., Color32::RED));
+            }
+        }
+
+        // Knobs to drag connections from on the rects near the pointer
+        let knob_reach = PORT_KNOB_DISTANCE + PORT_KNOB_RADIUS;
+        for (i, con_rect) in self.app_data.con_rects.iter().enumerate() {
+            let rect = to_screen.transform_rect(Rect::from_min_size(
+                pos2(con_rect.x, con_rect.y),
+                vec2(con_rect.width, con_rect.height),
+            ));
+            let near = ui_result
+                .hover_pos
+                .is_some_and(|pos| rect.expand(knob_reach).contains(pos));
+            let dragged_from = self.new_connection.as_ref().map(|c| c.from) == Some(i);
+            if !near && !dragged_from {
+                continue;
+            }
+            for (_, pos) in port_knobs(con_rect, &to_screen) {
+                painter.circle(pos, PORT_KNOB_RADIUS, Color32::WHITE, (1., Color32::BLUE));
+            }
+        }
+
+        let labels = label::place_labels(
+            &self.app_data.con_rects,
+            &self.app_data.connections,
+            &self.app_data.polylines,
+        );
+        for (connection, placement) in self.app_data.connections.iter().zip(labels) {
+            let (Some(text), Some(placement)) = (&connection.label, placement) else {
+                continue;
+            };
+            painter.text(
+                to_screen.transform_pos(to_pos2(placement.center)),
+                Align2::CENTER_CENTER,
+                text,
+                FontId::proportional(label::FONT_SIZE * to_screen.scale().y),
+                Color32::BLACK,
+            );
+        }
     }
 
-    fn draw_grid(
-        &mut self,
-        ui_result: &UiResult,
-        response: &Response,
-        painter: &Painter,
-        to_screen: &RectTransform,
-    ) {
+    fn draw_grid(&mut self, ui_result: &UiResult, painter: &Painter, to_screen: &RectTransform) {
+        // Visible part of the diagram
+        let visible = *to_screen.from();
         for grid_line in &self.app_data.grid.intervals_x {
             let line = Shape::line_segment(
                 [
-                    to_screen.transform_pos(pos2(*grid_line, response.rect.top())),
-                    to_screen.transform_pos(pos2(*grid_line, response.rect.bottom())),
+                    to_screen.transform_pos(pos2(*grid_line, visible.top())),
+                    to_screen.transform_pos(pos2(*grid_line, visible.bottom())),
                 ],
                 (1., Color32::LIGHT_GRAY),
             );
@@ -225,8 +692,8 @@ impl App {
         for grid_line in &self.app_data.grid.intervals_y {
             let line = Shape::line_segment(
                 [
-                    to_screen.transform_pos(pos2(response.rect.left(), *grid_line)),
-                    to_screen.transform_pos(pos2(response.rect.right(), *grid_line)),
+                    to_screen.transform_pos(pos2(visible.left(), *grid_line)),
+                    to_screen.transform_pos(pos2(visible.right(), *grid_line)),
                 ],
                 (1., Color32::LIGHT_GRAY),
             );
@@ -236,28 +703,21 @@ impl App {
         const MARKER_SIZE: f32 = 4.;
 
         for (i, grid_point) in self.app_data.grid.points.iter().enumerate() {
-            let rect = Rect {
-                min: Pos2::new(
-                    grid_point.pos.x as f32 - MARKER_SIZE,
-                    grid_point.pos.y as f32 - MARKER_SIZE,
-                ),
-                max: Pos2::new(
-                    grid_point.pos.x as f32 + MARKER_SIZE,
-                    grid_point.pos.y as f32 + MARKER_SIZE,
-                ),
-            };
+            // Markers keep their size on screen at any zoom.
+            let screen_pos = to_screen.transform_pos(to_pos2(grid_point.pos));
+            let rect = Rect::from_center_size(screen_pos, Vec2::splat(2. * MARKER_SIZE));
 
-            let hover = ui_result.hover_pos.is_some_and(|hov| {
-                to_screen.transform_pos(grid_point.pos).distance_sq(hov) < MARKER_SIZE.powi(2)
-            });
+            let hover = ui_result
+                .hover_pos
+                .is_some_and(|hov| screen_pos.distance_sq(hov) < MARKER_SIZE.powi(2));
 
             if hover {
                 for j in &grid_point.connect {
                     if let Some(grid_point_j) = self.app_data.grid.points.get(*j) {
                         let line = Shape::line_segment(
                             [
-                                to_screen.transform_pos(grid_point_j.pos),
-                                to_screen.transform_pos(grid_point.pos),
+                                to_screen.transform_pos(to_pos2(grid_point_j.pos)),
+                                to_screen.transform_pos(to_pos2(grid_point.pos)),
                             ],
                             (2., Color32::from_rgb(191, 0, 191)),
                         );
@@ -269,31 +729,27 @@ impl App {
             let visited = self
                 .app_data
                 .visited_nodes
-                .as_ref()
-                .is_some_and(|nodes| nodes.contains_key(&i));
+                .iter()
+                .any(|nodes| nodes.keys().any(|state| state.id == i));
 
             let color = if hover {
                 Color32::BLUE
             } else if visited {
                 Color32::DARK_GREEN
-            } else if self.app_data.start_nodes.iter().any(|j| i == *j) {
+            } else if self.app_data.start_nodes.contains(&i) {
                 Color32::RED
-            } else if self.app_data.goal_nodes.iter().any(|j| *j == i) {
+            } else if self.app_data.goal_nodes.contains(&i) {
                 Color32::GREEN
             } else {
                 Color32::LIGHT_GRAY
             };
 
-            painter.rect_stroke(
-                to_screen.transform_rect(rect),
-                0.,
-                (if hover { 2. } else { 1. }, color),
-            );
+            painter.rect_stroke(rect, 0., (if hover { 2. } else { 1. }, color));
 
             if self.show_grid_label {
                 let font = FontId::monospace(10.);
                 painter.text(
-                    to_screen.transform_pos(grid_point.pos),
+                    screen_pos,
                     Align2::CENTER_BOTTOM,
                     format!("{i}"),
                     font,
@@ -306,11 +762,12 @@ impl App {
             }
         }
 
-        for (i, node) in self.app_data.visited_nodes.iter().flatten() {
-            let to = to_screen.transform_pos(self.app_data.grid.points[*i].pos);
+        for (state, node) in self.app_data.visited_nodes.iter().flatten() {
+            let to = to_screen.transform_pos(to_pos2(self.app_data.grid.points[state.id].pos));
             if self.show_search_graph {
                 if let Some(came_from) = node.came_from {
-                    let from = to_screen.transform_pos(self.app_data.grid.points[came_from].pos);
+                    let from = to_screen
+                        .transform_pos(to_pos2(self.app_data.grid.points[came_from.id].pos));
                     let mid = (to + from.to_vec2()) / 2.;
                     let line =
                         Shape::line_segment([from, mid], (2., Color32::from_rgb(0, 127, 191)));
@@ -323,7 +780,7 @@ impl App {
             if self.show_grid_cost {
                 let font = FontId::monospace(10.);
                 painter.text(
-                    to_screen.transform_pos(to),
+                    to,
                     Align2::CENTER_TOP,
                     format!("{}", node.cost),
                     font,
@@ -335,17 +792,444 @@ impl App {
 }
 
 impl AppData {
-    fn new(mut con_rects: Vec<ConRect>) -> Self {
-        let grid = Grid::new(&mut con_rects);
+    fn new(diagram: Diagram) -> Self {
+        let Diagram {
+            rects: mut con_rects,
+            connections,
+            router: config,
+        } = diagram;
+        let grid = Grid::new(&mut con_rects, &config);
 
         Self {
             con_rects,
+            connections,
             grid,
             start_nodes: vec![],
             goal_nodes: vec![],
-            path: None,
+            paths: vec![],
+            polylines: vec![],
+            crossings: 0,
             selected_rect: None,
-            visited_nodes: None,
+            selected_connection: None,
+            visited_nodes: vec![],
+            config,
+        }
+    }
+
+    fn rebuild_grid(&mut self) {
+        self.grid = Grid::new(&mut self.con_rects, &self.config);
+    }
+
+    /// Add a rect of the default size centred at `center` and return its index.
+    fn add_rect(&mut self, center: Pos2) -> usize {
+        let min = center - DEFAULT_BOX_SIZE / 2.;
+        self.con_rects.push(ConRect::new(
+            min.x,
+            min.y,
+            DEFAULT_BOX_SIZE.x,
+            DEFAULT_BOX_SIZE.y,
+        ));
+        self.con_rects.len() - 1
+    }
+
+    /// Remove the rect at `index` along with the connections attached to it. The rects
+    /// after it move down by one, and so do the indices of the connections to them.
+    fn remove_rect(&mut self, index: usize) {
+        self.con_rects.remove(index);
+        self.selected_connection = None;
+        self.connections
+            .retain(|connection| connection.from != index && connection.to != index);
+        for connection in &mut self.connections {
+            for end in [&mut connection.from, &mut connection.to] {
+                if index < *end {
+                    *end -= 1;
+                }
+            }
+        }
+    }
+
+    /// Forget the routes, which no longer match the rects and connections.
+    fn clear_routes(&mut self) {
+        self.start_nodes.clear();
+        self.goal_nodes.clear();
+        self.paths.clear();
+        self.polylines.clear();
+        self.visited_nodes.clear();
+        self.crossings = 0;
+    }
+
+    fn rect_at(&self, pos: Pos2) -> Option<usize> {
+        self.con_rects
+            .iter()
+            .position(|rect| rect.contains(Point::new(pos.x, pos.y), 0.))
+    }
+
+    /// Route `connection` on its own, without adding it to the diagram.
+    fn route(&self, connection: &Connection) -> Option<Vec<Point>> {
+        let mut router = Router::new(&self.grid, &self.con_rects, self.config);
+        let route = router.route_all(std::slice::from_ref(connection)).pop()?;
+        route.ok().map(|route| route.polyline)
+    }
+
+    fn to_diagram(&self) -> Diagram {
+        Diagram {
+            rects: self.con_rects.clone(),
+            connections: self.connections.clone(),
+            router: self.config,
+        }
+    }
+
+    /// Area covered by the rects and the routes.
+    fn extent(&self) -> Option<(Point, Point)> {
+        let (mut min, mut max) = bounding_box(&self.con_rects)?;
+        for pos in self.polylines.iter().flatten().flatten() {
+            min = Point::new(min.x.min(pos.x), min.y.min(pos.y));
+            max = Point::new(max.x.max(pos.x), max.y.max(pos.y));
+        }
+        Some((min, max))
+    }
+
+    fn to_svg(&self) -> String {
+        svg::to_svg(&self.con_rects, &self.connections, &self.polylines)
+    }
+
+    /// Route every connection one after another. A failed connection does not stop the
+    /// others from being routed; the errors are collected and returned together.
+    fn search(&mut self) -> Result<(), String> {
+        let mut router = Router::new(&self.grid, &self.con_rects, self.config);
+
+        self.start_nodes.clear();
+        self.goal_nodes.clear();
+        for connection in &self.connections {
+            if let Some(rect) = self.con_rects.get(connection.from) {
+                self.start_nodes
+                    .extend(rect.side_connectors(&connection.from_sides));
+            }
+            if let Some(rect) = self.con_rects.get(connection.to) {
+                self.goal_nodes
+                    .extend(rect.side_connectors(&connection.to_sides));
+            }
+        }
+
+        let routes = router.search_all(&self.connections, &mut self.visited_nodes);
+        self.polylines = nudge_routes(&routes, &self.con_rects, &self.config);
+        let mut errors = vec![];
+        self.paths = routes
+            .into_iter()
+            .map(|route| route.map_err(|e| errors.push(e.to_string())).ok())
+            .collect();
+        let polylines: Vec<_> = self.polylines.iter().flatten().cloned().collect();
+        self.crossings = count_crossings(&polylines);
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+}
+
+/// Returns true if any setting was changed.
+fn config_ui(ui: &mut Ui, config: &mut RouterConfig) -> bool {
+    let mut changed = false;
+    ui.label("Collision margin:");
+    changed |= ui
+        .add(Slider::new(&mut config.collision_margin, 0.0..=20.0))
+        .changed();
+    // The margins are kept in decreasing order, as the grid and the router need them.
+    let min_port_offset = config.min_port_offset();
+    ui.label("Port offset:");
+    changed |= ui
+        .add(Slider::new(
+            &mut config.port_offset,
+            min_port_offset..=min_port_offset.max(40.),
+        ))
+        .changed();
+    let min_grid_margin = config.min_grid_margin();
+    ui.label("Grid margin:");
+    changed |= ui
+        .add(Slider::new(
+            &mut config.grid_margin,
+            min_grid_margin..=min_grid_margin.max(50.),
+        ))
+        .changed();
+    let clamped = config.clamped();
+    if clamped != *config {
+        *config = clamped;
+        changed = true;
+    }
+    ui.label("Grid padding:");
+    changed |= ui
+        .add(Slider::new(&mut config.grid_padding, 0.0..=200.0))
+        .changed();
+    ui.label("Intersection tolerance:");
+    changed |= ui
+        .add(Slider::new(&mut config.intersection_tolerance, 0.1..=5.0))
+        .changed();
+    ui.label("Bend penalty:");
+    changed |= ui
+        .add(Slider::new(&mut config.bend_penalty, 0.0..=200.0))
+        .changed();
+    ui.label("Crossing penalty:");
+    changed |= ui
+        .add(Slider::new(&mut config.crossing_penalty, 0.0..=200.0))
+        .changed();
+    ui.label("Overlap penalty:");
+    changed |= ui
+        .add(Slider::new(&mut config.overlap_penalty, 0.0..=200.0))
+        .changed();
+    ui.label("Side penalty:");
+    changed |= ui
+        .add(Slider::new(&mut config.side_penalty, 0.0..=200.0))
+        .changed();
+    ui.label("Min stub length:");
+    changed |= ui
+        .add(Slider::new(&mut config.min_stub_length, 0.0..=50.0))
+        .changed();
+    ui.label("Iteration limit:");
+    changed |= ui
+        .add(Slider::new(&mut config.iteration_limit, 1..=100).logarithmic(true))
+        .changed();
+    ui.label("Nudge spacing:");
+    changed |= ui
+        .add(Slider::new(&mut config.nudge_spacing, 0.0..=20.0))
+        .changed();
+    ui.horizontal(|ui| {
+        changed |= ui
+            .radio_value(
+                &mut config.strategy,
+                RoutingStrategy::Sequential,
+                "Sequential",
+            )
+            .changed();
+        changed |= ui
+            .radio_value(
+                &mut config.strategy,
+                RoutingStrategy::Negotiated,
+                "Negotiated",
+            )
+            .changed();
+    });
+    if config.strategy == RoutingStrategy::Negotiated {
+        ui.label("Negotiation iterations:");
+        changed |= ui
+            .add(Slider::new(&mut config.negotiation_iterations, 1..=50))
+            .changed();
+        ui.label("History cost:");
+        changed |= ui
+            .add(Slider::new(&mut config.history_cost, 0.0..=200.0))
+            .changed();
+    }
+    ui.horizontal(|ui| {
+        changed |= ui
+            .radio_value(&mut config.algorithm, SearchAlgorithm::Dijkstra, "Dijkstra")
+            .changed();
+        changed |= ui
+            .radio_value(&mut config.algorithm, SearchAlgorithm::AStar, "A*")
+            .changed();
+    });
+    if ui.button("Reset to defaults").clicked() {
+        *config = RouterConfig::default();
+        changed = true;
+    }
+    changed
+}
+
+/// Drawing style controls of a single connection. Styles do not affect routing.
+fn connection_ui(ui: &mut Ui, connection: &mut Connection) {
+    ui.label(format!("{} -> {}", connection.from, connection.to));
+    let shape = &mut connection.style.shape;
+    ui.horizontal(|ui| {
+        ui.radio_value(shape, LineShape::Sharp, "Sharp");
+        let rounded = matches!(shape, LineShape::Rounded { .. });
+        if ui.radio(rounded, "Rounded").clicked() && !rounded {
+            *shape = LineShape::Rounded {
+                radius: DEFAULT_CORNER_RADIUS,
+            };
+        }
+        ui.radio_value(shape, LineShape::Spline, "Spline");
+    });
+    if let LineShape::Rounded { radius } = shape {
+        ui.add(Slider::new(radius, 0.0..=50.0).text("Radius"));
+    }
+    let pattern = &mut connection.style.pattern;
+    ui.horizontal(|ui| {
+        ui.radio_value(pattern, LinePattern::Solid, "Solid");
+        ui.radio_value(pattern, LinePattern::Dashed, "Dashed");
+        ui.radio_value(pattern, LinePattern::Dotted, "Dotted");
+    });
+    ui.horizontal(|ui| {
+        marker_ui(ui, "Start", &mut connection.style.start_marker);
+        marker_ui(ui, "End", &mut connection.style.end_marker);
+    });
+    ui.horizontal(|ui| {
+        ui.label("Label:");
+        let mut text = connection.label.clone().unwrap_or_default();
+        if ui.text_edit_singleline(&mut text).changed() {
+            connection.label = (!text.is_empty()).then_some(text);
+        }
+    });
+    if connection.label.is_some() {
+        let position = &mut connection.style.label_position;
+        ui.horizontal(|ui| {
+            ui.radio_value(position, LabelPosition::LongestSegment, "Longest");
+            ui.radio_value(position, LabelPosition::Start, "Start");
+            ui.radio_value(position, LabelPosition::Middle, "Middle");
+            ui.radio_value(position, LabelPosition::End, "End");
+        });
+    }
+}
+
+fn marker_ui(ui: &mut Ui, label: &str, marker: &mut Marker) {
+    const MARKERS: [(Marker, &str); 5] = [
+        (Marker::None, "None"),
+        (Marker::Arrow, "Arrow"),
+        (Marker::OpenArrow, "Open arrow"),
+        (Marker::Diamond, "Diamond"),
+        (Marker::Circle, "Circle"),
+    ];
+    let selected = MARKERS
+        .iter()
+        .find(|(m, _)| m == marker)
+        .map_or("", |(_, name)| name);
+    ComboBox::from_label(label)
+        .selected_text(selected)
+        .show_ui(ui, |ui| {
+            for (value, name) in MARKERS {
+                ui.selectable_value(marker, value, name);
+            }
+        });
+}
+
+fn default_diagram() -> Diagram {
+    Diagram {
+        rects: vec![
+            ConRect::new(130., 70., 140., 50.),
+            ConRect::new(420., 120., 90., 30.),
+            ConRect::new(260., 420., 150., 60.),
+        ],
+        connections: vec![
+            Connection::new(0, 1),
+            Connection::new(1, 2),
+            Connection::new(0, 2),
+        ],
+        router: RouterConfig::default(),
+    }
+}
+
+fn load_diagram(path: &Path) -> Result<Diagram, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+    serde_json::from_str(&text).map_err(|e| format!("Failed to parse {}: {e}", path.display()))
+}
+
+fn save_diagram(path: &Path, diagram: &Diagram) -> Result<(), String> {
+    let text = serde_json::to_string_pretty(diagram).map_err(|e| e.to_string())?;
+    std::fs::write(path, text).map_err(|e| format!("Failed to write {}: {e}", path.display()))
+}
+
+/// Diagram position of `handle` on `rect`.
+fn handle_pos(rect: &ConRect, handle: Vec2) -> Pos2 {
+    pos2(
+        rect.x + rect.width * (handle.x + 1.) / 2.,
+        rect.y + rect.height * (handle.y + 1.) / 2.,
+    )
+}
+
+/// Screen area of the handle drawn at `screen_pos`.
+fn handle_rect(screen_pos: Pos2) -> Rect {
+    Rect::from_center_size(screen_pos, Vec2::splat(HANDLE_SIZE))
+}
+
+fn handle_cursor(handle: Vec2) -> CursorIcon {
+    if handle.x == 0. {
+        CursorIcon::ResizeVertical
+    } else if handle.y == 0. {
+        CursorIcon::ResizeHorizontal
+    } else if handle.x == handle.y {
+        CursorIcon::ResizeNwSe
+    } else {
+        CursorIcon::ResizeNeSw
+    }
+}
+
+/// Move the edges of `rect` that `handle` is on to `pos`, keeping the opposite edges in
+/// place and the size at least [`MIN_BOX_SIZE`].
+fn resize(rect: &mut ConRect, handle: Vec2, pos: Pos2) {
+    if handle.x < 0. {
+        let right = rect.x + rect.width;
+        rect.x = pos.x.min(right - MIN_BOX_SIZE);
+        rect.width = right - rect.x;
+    } else if 0. < handle.x {
+        rect.width = (pos.x - rect.x).max(MIN_BOX_SIZE);
+    }
+    if handle.y < 0. {
+        let bottom = rect.y + rect.height;
+        rect.y = pos.y.min(bottom - MIN_BOX_SIZE);
+        rect.height = bottom - rect.y;
+    } else if 0. < handle.y {
+        rect.height = (pos.y - rect.y).max(MIN_BOX_SIZE);
+    }
+}
+
+/// Screen positions of the knobs on each port of `rect`.
+fn port_knobs(rect: &ConRect, to_screen: &RectTransform) -> Vec<(Side, Pos2)> {
+    let offset = PORT_KNOB_DISTANCE / to_screen.scale().x;
+    Side::ALL
+        .into_iter()
+        .flat_map(|side| {
+            rect.port_positions(side, offset)
+                .into_iter()
+                .map(move |pos| (side, to_screen.transform_pos(to_pos2(pos))))
+        })
+        .collect()
+}
+
+/// Screen positions along the drawn path of `connection` routed along `polyline`.
+fn screen_path(
+    connection: &Connection,
+    polyline: &[Point],
+    to_screen: &RectTransform,
+) -> Vec<Pos2> {
+    let path = connection.style.shape.path(polyline);
+    style::flatten(&path)
+        .into_iter()
+        .map(|pos| to_screen.transform_pos(to_pos2(pos)))
+        .collect()
+}
+
+/// Distance from `pos` to the line segment from `a` to `b`.
+fn segment_distance(pos: Pos2, a: Pos2, b: Pos2) -> f32 {
+    let ab = b - a;
+    let t = if ab.length_sq() == 0. {
+        0.
+    } else {
+        ((pos - a).dot(ab) / ab.length_sq()).clamp(0., 1.)
+    };
+    pos.distance(a + ab * t)
+}
+
+fn to_pos2(pos: Point) -> Pos2 {
+    pos2(pos.x, pos.y)
+}
+
+/// Screen shape of a connector end marker, in the colour of `stroke`.
+fn marker_shape(marker: &MarkerShape, to_screen: &RectTransform, stroke: Stroke) -> Shape {
+    let to_screen_all = |points: &[Point]| {
+        points
+            .iter()
+            .map(|pos| to_screen.transform_pos(to_pos2(*pos)))
+            .collect()
+    };
+    match marker {
+        MarkerShape::Polygon(points) => {
+            Shape::convex_polygon(to_screen_all(points), stroke.color, Stroke::NONE)
         }
+        MarkerShape::Polyline(points) => Shape::line(to_screen_all(points), stroke),
+        MarkerShape::Circle { center, radius } => Shape::circle_filled(
+            to_screen.transform_pos(to_pos2(*center)),
+            radius * to_screen.scale().x,
+            stroke.color,
+        ),
     }
 }
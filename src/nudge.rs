@@ -0,0 +1,481 @@
+//! Post-routing pass that separates connectors running along the same grid line.
+//!
+//! Routes found independently on the same [`Grid`](crate::Grid) often share grid edges, so
+//! their polylines lie on top of each other. This pass finds collinear overlapping segments
+//! of different routes and spreads them apart perpendicular to their direction. The order
+//! within each bundle is decided by where the routes diverge, and bundles do not move past
+//! the bends of other routes, so that nudging does not add crossings. The only crossings it
+//! reveals are those of routes that swap sides along a segment they share, which were
+//! hidden in the overlap.
+
+use std::collections::HashMap;
+
+use crate::{
+    geom::{simplify, Axis, Point},
+    ConRect, Route, RouteError, RouterConfig,
+};
+
+const EPSILON: f32 = 1e-3;
+
+/// Unit direction of an axis-aligned segment, as signs of x and y.
+type Dir = (i32, i32);
+
+struct Segment {
+    route: usize,
+    /// Index of the first point of the segment in the route's polyline
+    index: usize,
+    axis: Axis,
+    /// Coordinate on the perpendicular axis, i.e. y of a horizontal segment
+    pos: f32,
+    min: f32,
+    max: f32,
+    /// The first and last segments are attached to connectors and cannot move.
+    movable: bool,
+    /// How far the segment can move in either direction before the first or last segment
+    /// gets shorter than the minimum stub length
+    stub_range: (f32, f32),
+}
+
+impl Segment {
+    fn overlaps(&self, other: &Self) -> bool {
+        self.axis == other.axis
+            && (self.pos - other.pos).abs() < EPSILON
+            && EPSILON < self.max.min(other.max) - self.min.max(other.min)
+    }
+}
+
+/// [`nudge`] the polylines of the connections in `routes` that were routed. Returns one
+/// entry per route in the same order, `None` for the ones that failed.
+pub fn nudge_routes(
+    routes: &[Result<Route, RouteError>],
+    con_rects: &[ConRect],
+    config: &RouterConfig,
+) -> Vec<Option<Vec<Point>>> {
+    let routed: Vec<_> = routes
+        .iter()
+        .flatten()
+        .map(|route| route.polyline.clone())
+        .collect();
+    let mut nudged = nudge(&routed, con_rects, config).into_iter();
+    routes
+        .iter()
+        .map(|route| route.as_ref().ok().and_then(|_| nudged.next()))
+        .collect()
+}
+
+/// Simplify `polylines` and move apart the segments they share, keeping them at least
+/// `config.collision_margin` away from `con_rects`. Returns the polylines in the same order.
+pub fn nudge(
+    polylines: &[Vec<Point>],
+    con_rects: &[ConRect],
+    config: &RouterConfig,
+) -> Vec<Vec<Point>> {
+    let config = &config.clamped();
+    let mut polylines: Vec<_> = polylines.iter().map(|p| simplify(p)).collect();
+    if config.nudge_spacing <= 0. {
+        return polylines;
+    }
+
+    let stub = config.min_stub_length - config.port_offset;
+    let segments: Vec<_> = polylines
+        .iter()
+        .enumerate()
+        .flat_map(|(route, polyline)| {
+            let last = polyline.len().saturating_sub(2);
+            polyline.windows(2).enumerate().map(move |(index, w)| {
+                let axis = Axis::between(w[0], w[1]);
+                let (pos, a, b) = match axis {
+                    Axis::Horizontal => (w[0].y, w[0].x, w[1].x),
+                    Axis::Vertical => (w[0].x, w[0].y, w[1].y),
+                };
+                let across = |p: Point| match axis {
+                    Axis::Horizontal => p.y,
+                    Axis::Vertical => p.x,
+                };
+                let mut stub_range = (f32::NEG_INFINITY, f32::INFINITY);
+                if index == 1 {
+                    stub_range = limit_stub(stub_range, pos - across(polyline[0]), stub);
+                }
+                if index + 1 == last {
+                    stub_range = limit_stub(stub_range, pos - across(polyline[last + 1]), stub);
+                }
+                Segment {
+                    route,
+                    index,
+                    axis,
+                    pos,
+                    min: a.min(b),
+                    max: a.max(b),
+                    movable: 0 < index && index < last,
+                    stub_range,
+                }
+            })
+        })
+        .collect();
+
+    // Group segments that transitively overlap each other
+    let mut parent: Vec<_> = (0..segments.len()).collect();
+    for i in 0..segments.len() {
+        for j in i + 1..segments.len() {
+            if segments[i].route != segments[j].route && segments[i].overlaps(&segments[j]) {
+                let (ri, rj) = (find(&mut parent, i), find(&mut parent, j));
+                parent[ri] = rj;
+            }
+        }
+    }
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in 0..segments.len() {
+        let root = find(&mut parent, i);
+        groups.entry(root).or_default().push(i);
+    }
+
+    let mut offsets = vec![0.; segments.len()];
+    for group in groups.values().filter(|group| 1 < group.len()) {
+        // Count how many others each segment should be placed beyond
+        let mut ranked: Vec<_> = group
+            .iter()
+            .map(|&i| {
+                let score: i32 = group
+                    .iter()
+                    .filter(|&&j| i != j && segments[i].overlaps(&segments[j]))
+                    .map(|&j| compare(&polylines, &segments[i], &segments[j]))
+                    .sum();
+                (score, segments[i].route, i)
+            })
+            .collect();
+        ranked.sort();
+        let order: Vec<_> = ranked.into_iter().map(|(_, _, i)| i).collect();
+        place(&segments, &order, con_rects, config, &mut offsets);
+    }
+
+    for (segment, offset) in segments.iter().zip(offsets) {
+        if offset == 0. {
+            continue;
+        }
+        let polyline = &mut polylines[segment.route];
+        for pos in &mut polyline[segment.index..=segment.index + 1] {
+            match segment.axis {
+                Axis::Horizontal => pos.y += offset,
+                Axis::Vertical => pos.x += offset,
+            }
+        }
+    }
+
+    polylines
+}
+
+fn find(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+/// Assign offsets to the segments of a group, sorted by increasing coordinate.
+fn place(
+    segments: &[Segment],
+    order: &[usize],
+    con_rects: &[ConRect],
+    config: &RouterConfig,
+    offsets: &mut [f32],
+) {
+    let first = &segments[order[0]];
+    let (min, max) = order
+        .iter()
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |acc, &i| {
+            (acc.0.min(segments[i].min), acc.1.max(segments[i].max))
+        });
+    let (rect_lo, rect_hi) = free_range(first.axis, first.pos, min, max, con_rects, config);
+    let (route_lo, route_hi) = routes_range(segments, order, min, max, config);
+    let (lo, hi) = (rect_lo.max(route_lo), rect_hi.min(route_hi));
+
+    let gaps = (order.len() - 1) as f32;
+    let spacing = config.nudge_spacing.min((hi - lo) / gaps).max(0.);
+    let total = spacing * gaps;
+    let mut start = (-total / 2.).clamp(lo, (hi - total).max(lo));
+
+    // Segments attached to connectors stay in place, so centre the bundle on one of them.
+    if let Some(rank) = order.iter().position(|&i| !segments[i].movable) {
+        start = -(rank as f32) * spacing;
+    }
+
+    for (rank, &i) in order.iter().enumerate() {
+        if segments[i].movable {
+            let (stub_lo, stub_hi) = segments[i].stub_range;
+            offsets[i] = (start + rank as f32 * spacing)
+                .clamp(lo, hi)
+                .clamp(stub_lo, stub_hi);
+        }
+    }
+}
+
+/// Narrow `range` so that a segment `offset` away from the connector at the other end of
+/// the stub leading to it cannot move closer to that connector than `stub`.
+fn limit_stub(range: (f32, f32), offset: f32, stub: f32) -> (f32, f32) {
+    let slack = (offset.abs() - stub).max(0.);
+    if 0. < offset {
+        (range.0.max(-slack), range.1)
+    } else {
+        (range.0, range.1.min(slack))
+    }
+}
+
+/// How far a segment at `pos` spanning `min..max` can move in either direction before it
+/// comes closer than the collision margin to a rect.
+fn free_range(
+    axis: Axis,
+    pos: f32,
+    min: f32,
+    max: f32,
+    con_rects: &[ConRect],
+    config: &RouterConfig,
+) -> (f32, f32) {
+    let margin = config.collision_margin;
+    let mut lo = f32::NEG_INFINITY;
+    let mut hi = f32::INFINITY;
+    for rect in con_rects {
+        let (span_min, span_max, near, far) = match axis {
+            Axis::Horizontal => (rect.x, rect.x + rect.width, rect.y, rect.y + rect.height),
+            Axis::Vertical => (rect.y, rect.y + rect.height, rect.x, rect.x + rect.width),
+        };
+        if max <= span_min - margin || span_max + margin <= min {
+            continue;
+        }
+        if far + margin <= pos {
+            lo = lo.max(far + margin - pos);
+        } else if pos <= near - margin {
+            hi = hi.min(near - margin - pos);
+        }
+    }
+    (lo, hi)
+}
+
+/// How far the segments in `group`, spanning `min..max`, can move in either direction
+/// without passing a bend or end of another route, or a segment of another route running
+/// alongside them. Moving past those would add crossings.
+fn routes_range(
+    segments: &[Segment],
+    group: &[usize],
+    min: f32,
+    max: f32,
+    config: &RouterConfig,
+) -> (f32, f32) {
+    let first = &segments[group[0]];
+    let gap = config.nudge_spacing / 2.;
+    let mut lo = f32::NEG_INFINITY;
+    let mut hi = f32::INFINITY;
+    let mut limit = |across: f32| {
+        let offset = across - first.pos;
+        if EPSILON <= offset {
+            hi = hi.min((offset - gap).max(0.));
+        } else if offset <= -EPSILON {
+            lo = lo.max((offset + gap).min(0.));
+        }
+    };
+    for (i, segment) in segments.iter().enumerate() {
+        if group.contains(&i) {
+            continue;
+        }
+        if segment.axis == first.axis {
+            if min < segment.max + EPSILON && segment.min - EPSILON < max {
+                limit(segment.pos);
+            }
+        } else if min + EPSILON < segment.pos && segment.pos < max - EPSILON {
+            limit(segment.min);
+            limit(segment.max);
+        }
+    }
+    (lo, hi)
+}
+
+/// Returns 1 if the segment `a` should be placed at a larger coordinate than the
+/// overlapping segment `b`, -1 if smaller and 0 if it does not matter.
+fn compare(polylines: &[Vec<Point>], a: &Segment, b: &Segment) -> i32 {
+    let pa = &polylines[a.route];
+    let pb = &polylines[b.route];
+    let da = direction(pa[a.index], pa[a.index + 1]);
+    let db = direction(pb[b.index], pb[b.index + 1]);
+    let rev_a: Vec<_> = pa.iter().rev().copied().collect();
+    let rev_b: Vec<_> = pb.iter().rev().copied().collect();
+    let rev_ia = pa.len() - 2 - a.index;
+    let rev_ib = pb.len() - 2 - b.index;
+
+    // Walk both routes in the direction of `a`, first forward, then backward.
+    let (forward, backward) = if da == db {
+        (
+            diverge(pa, a.index, pb, b.index),
+            -diverge(&rev_a, rev_ia, &rev_b, rev_ib),
+        )
+    } else {
+        (
+            diverge(pa, a.index, &rev_b, rev_ib),
+            -diverge(&rev_a, rev_ia, pb, b.index),
+        )
+    };
+    let side = if forward != 0 { forward } else { backward };
+
+    // The positive side of direction (x, y) is (-y, x).
+    match a.axis {
+        Axis::Horizontal => side * da.0,
+        Axis::Vertical => -side * da.1,
+    }
+}
+
+/// Follow two routes from their overlapping segments `a[i]..a[i + 1]` and
+/// `b[j]..b[j + 1]`, which run in the same direction, until they part. Returns 1 if `a`
+/// leaves to the positive side of `b`, -1 if to the negative side and 0 if undecided.
+fn diverge(a: &[Point], mut i: usize, b: &[Point], mut j: usize) -> i32 {
+    loop {
+        let d = direction(a[i], a[i + 1]);
+        let end_a = along(d, a[i + 1]);
+        let end_b = along(d, b[j + 1]);
+        let next_a = a.get(i + 2).map(|p| direction(a[i + 1], *p));
+        let next_b = b.get(j + 2).map(|p| direction(b[j + 1], *p));
+        let (turn_a, turn_b) = if (end_a - end_b).abs() < EPSILON {
+            if next_a.is_some() && next_a == next_b {
+                i += 1;
+                j += 1;
+                continue;
+            }
+            (turn(d, next_a), turn(d, next_b))
+        } else if end_a < end_b {
+            // `a` turns or ends first while `b` goes straight on
+            (turn(d, next_a), 0)
+        } else {
+            (0, turn(d, next_b))
+        };
+        return (turn_a - turn_b).signum();
+    }
+}
+
+fn direction(from: Point, to: Point) -> Dir {
+    (
+        (to.x - from.x).partial_cmp(&0.).map_or(0, |o| o as i32),
+        (to.y - from.y).partial_cmp(&0.).map_or(0, |o| o as i32),
+    )
+}
+
+fn along(d: Dir, pos: Point) -> f32 {
+    d.0 as f32 * pos.x + d.1 as f32 * pos.y
+}
+
+/// 1 if `next` turns to the positive side of `d`, -1 if negative and 0 if it goes straight
+/// or there is no next segment.
+fn turn(d: Dir, next: Option<Dir>) -> i32 {
+    next.map_or(0, |n| (d.0 * n.1 - d.1 * n.0).signum())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{count_crossings, search::tests::random_diagram, Grid, Router};
+
+    /// Number of pairs of segments of `a` and `b` that overlap along the same line.
+    fn shared_segments(a: &[Point], b: &[Point]) -> usize {
+        let segments = |polyline: &[Point]| -> Vec<Segment> {
+            polyline
+                .windows(2)
+                .map(|w| {
+                    let axis = Axis::between(w[0], w[1]);
+                    let (pos, a, b) = match axis {
+                        Axis::Horizontal => (w[0].y, w[0].x, w[1].x),
+                        Axis::Vertical => (w[0].x, w[0].y, w[1].y),
+                    };
+                    Segment {
+                        route: 0,
+                        index: 0,
+                        axis,
+                        pos,
+                        min: a.min(b),
+                        max: a.max(b),
+                        movable: true,
+                        stub_range: (0., 0.),
+                    }
+                })
+                .collect()
+        };
+        let (a, b) = (segments(a), segments(b));
+        a.iter()
+            .map(|sa| b.iter().filter(|sb| sa.overlaps(sb)).count())
+            .sum()
+    }
+
+    /// Routes that share a segment may have to cross where they part, if they swap sides
+    /// along it. Such crossings are hidden before nudging, since overlapping segments do not
+    /// count as crossing. Any other crossing added by nudging is a bad order or move.
+    #[test]
+    fn nudging_only_reveals_crossings_of_shared_segments() {
+        // Without the penalties, routes share grid edges freely and leave work for nudging.
+        let config = RouterConfig {
+            overlap_penalty: 0.,
+            crossing_penalty: 0.,
+            ..RouterConfig::default()
+        };
+        let mut moved = false;
+        for seed in 0..50 {
+            let (mut rects, connections) = random_diagram(seed);
+            let grid = Grid::new(&mut rects, &config);
+            let routed: Vec<_> = Router::new(&grid, &rects, config)
+                .route_all(&connections)
+                .into_iter()
+                .map(|route| route.unwrap().polyline)
+                .collect();
+            let nudged = nudge(&routed, &rects, &config);
+            moved |= nudged != routed;
+            for i in 0..routed.len() {
+                for j in i + 1..routed.len() {
+                    let crossings = |polylines: &[Vec<Point>]| {
+                        count_crossings(&[polylines[i].clone(), polylines[j].clone()])
+                    };
+                    let shared = shared_segments(&routed[i], &routed[j]);
+                    assert!(
+                        crossings(&nudged) <= crossings(&routed) + shared,
+                        "seed {seed}, routes {i} and {j}: {} crossings after nudging, {} \
+                         before, {shared} shared segments",
+                        crossings(&nudged),
+                        crossings(&routed)
+                    );
+                }
+            }
+        }
+        assert!(moved, "no route was nudged");
+    }
+
+    #[test]
+    fn shared_segments_are_moved_apart() {
+        let rects = [
+            ConRect::new(0., 0., 40., 40.),
+            ConRect::new(0., 100., 40., 40.),
+            ConRect::new(300., 0., 40., 40.),
+            ConRect::new(300., 100., 40., 40.),
+        ];
+        // Both routes run along y = 70 between x = 100 and x = 200.
+        let polylines = [
+            vec![
+                Point::new(44., 20.),
+                Point::new(100., 20.),
+                Point::new(100., 70.),
+                Point::new(200., 70.),
+                Point::new(200., 120.),
+                Point::new(296., 120.),
+            ],
+            vec![
+                Point::new(44., 120.),
+                Point::new(100., 120.),
+                Point::new(100., 70.),
+                Point::new(200., 70.),
+                Point::new(200., 20.),
+                Point::new(296., 20.),
+            ],
+        ];
+        let config = RouterConfig::default();
+        let nudged = nudge(&polylines, &rects, &config);
+        let middle_y = |polyline: &[Point]| polyline[2].y;
+        assert_eq!(
+            (middle_y(&nudged[0]) - middle_y(&nudged[1])).abs(),
+            config.nudge_spacing
+        );
+        assert_eq!(count_crossings(&nudged), count_crossings(&polylines));
+    }
+}
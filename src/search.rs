@@ -1,19 +1,47 @@
 use std::collections::{BinaryHeap, HashMap, HashSet};
 
-use super::AppData;
+use crate::{
+    geom::{count_crossings, simplify, Axis, Point},
+    ConRect, Connection, Grid, RouteError, RouterConfig, RoutingStrategy, SearchAlgorithm, Side,
+};
 
-pub(crate) const COLLISION_MARGIN: f32 = 2.;
+/// How much the cost of currently shared edges grows with each negotiation pass
+const PRESENT_FACTOR_GROWTH: f32 = 1.5;
+
+/// Slack in comparing stub lengths, so that a grid line exactly `min_stub_length` away from
+/// a rect counts as far enough despite rounding
+const STUB_TOLERANCE: f32 = 1e-3;
+
+/// A node in the search space: a grid node together with the axis it was entered along.
+/// Start nodes have no incoming axis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SearchState {
+    pub id: usize,
+    pub axis: Option<Axis>,
+}
+
+impl SearchState {
+    pub fn new(id: usize, axis: Option<Axis>) -> Self {
+        Self { id, axis }
+    }
+}
 
 #[derive(Debug, Clone, Copy)]
 struct SearchNode {
-    id: usize,
+    state: SearchState,
+    /// Accumulated cost from the start
     cost: f32,
-    came_from: Option<usize>,
+    /// Order in the open set: `cost` plus the heuristic estimate to the goal
+    priority: f32,
+    came_from: Option<SearchState>,
+    /// Whether this is a goal connector whose side penalty has been paid, so the search
+    /// can finish here.
+    arrived: bool,
 }
 
 impl std::cmp::PartialEq for SearchNode {
     fn eq(&self, other: &Self) -> bool {
-        self.cost == other.cost
+        self.priority == other.priority
     }
 }
 
@@ -21,128 +49,997 @@ impl std::cmp::Eq for SearchNode {}
 
 impl std::cmp::PartialOrd for SearchNode {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        other.cost.partial_cmp(&self.cost)
+        Some(self.cmp(other))
     }
 }
 
 impl std::cmp::Ord for SearchNode {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         other
-            .cost
-            .partial_cmp(&self.cost)
+            .priority
+            .partial_cmp(&self.priority)
             .unwrap_or(std::cmp::Ordering::Equal)
     }
 }
 
+#[derive(Debug, Clone, Copy)]
 pub struct VisitedNode {
     pub cost: f32,
-    pub came_from: Option<usize>,
+    pub came_from: Option<SearchState>,
 }
 
 impl VisitedNode {
-    pub fn new(cost: f32, came_from: Option<usize>) -> Self {
+    pub fn new(cost: f32, came_from: Option<SearchState>) -> Self {
         Self { cost, came_from }
     }
 }
 
-pub type VisitedMap = HashMap<usize, VisitedNode>;
-
-impl AppData {
-    pub(super) fn search(&mut self) -> Result<(), String> {
-        if let &[ref first, ref second, ..] = &self.con_rects[..] {
-            let mut visited = VisitedMap::new();
-            let mut next_set = BinaryHeap::new();
-            let start_ids = first.connectors();
-            let goal_ids = second.connectors();
-            self.start_nodes = start_ids.clone();
-            for start_id in start_ids {
-                next_set.push(SearchNode {
-                    id: start_id,
-                    cost: 0.,
-                    came_from: None,
-                });
-                visited.insert(start_id, VisitedNode::new(0., None));
+pub type VisitedMap = HashMap<SearchState, VisitedNode>;
+
+/// A routed connection.
+#[derive(Debug, Clone)]
+pub struct Route {
+    /// Grid node ids from the source connector to the target connector.
+    pub path: Vec<usize>,
+    /// Positions of the connectors at both ends and of the bends in between.
+    pub polyline: Vec<Point>,
+}
+
+impl Route {
+    pub fn new(path: Vec<usize>, grid: &Grid) -> Self {
+        let polyline = simplify(&grid.polyline(&path));
+        Self { path, polyline }
+    }
+}
+
+/// Finds paths for connections on a [`Grid`], avoiding every rect it was built from.
+///
+/// Routes added with [`Self::add_route`] are soft obstacles for later searches: crossing
+/// them costs `crossing_penalty` and running along them costs `overlap_penalty` per edge.
+/// The negotiated strategy scales the overlap cost up on every pass and adds a history cost
+/// to edges that have been shared before.
+pub struct Router<'a> {
+    grid: &'a Grid,
+    con_rects: &'a [ConRect],
+    obstructed: HashSet<usize>,
+    /// Side of its rect that each connector is on
+    connector_sides: HashMap<usize, Side>,
+    config: RouterConfig,
+    /// Number of added routes using each grid edge, keyed by node ids in increasing order
+    used_edges: HashMap<(usize, usize), u32>,
+    /// Number of added routes passing straight through a grid node along an axis
+    passing: HashMap<(usize, Axis), u32>,
+    /// Accumulated congestion cost of each grid edge in negotiated routing
+    history: HashMap<(usize, usize), f32>,
+    /// Multiplier of `overlap_penalty` in negotiated routing
+    present_factor: f32,
+}
+
+impl<'a> Router<'a> {
+    pub fn new(grid: &'a Grid, con_rects: &'a [ConRect], config: RouterConfig) -> Self {
+        let config = config.clamped();
+        let mut obstructed = HashSet::new();
+        for rect in con_rects {
+            for (j, pt) in grid.points.iter().enumerate() {
+                if rect.contains(pt.pos, config.collision_margin) {
+                    obstructed.insert(j);
+                }
             }
+        }
 
-            self.goal_nodes = goal_ids.clone();
+        let connector_sides = con_rects
+            .iter()
+            .flat_map(|rect| rect.cons.iter().map(|(side, id)| (*id, *side)))
+            .collect();
 
-            let mut obstructed = HashSet::new();
-            for rect in self.con_rects.iter() {
-                for (j, pt) in self.grid.points.iter().enumerate() {
-                    if rect.x - COLLISION_MARGIN <= pt.pos.x
-                        && pt.pos.x < rect.x + rect.width + COLLISION_MARGIN
-                        && rect.y - COLLISION_MARGIN <= pt.pos.y
-                        && pt.pos.y < rect.y + rect.height + COLLISION_MARGIN
-                    {
-                        obstructed.insert(j);
-                    }
+        Self {
+            grid,
+            con_rects,
+            obstructed,
+            connector_sides,
+            config,
+            used_edges: HashMap::new(),
+            passing: HashMap::new(),
+            history: HashMap::new(),
+            present_factor: 1.,
+        }
+    }
+
+    /// Route every connection with the configured [`RoutingStrategy`]. A failed connection
+    /// does not stop the others from being routed.
+    pub fn route_all(&mut self, connections: &[Connection]) -> Vec<Result<Route, RouteError>> {
+        self.search_all(connections, &mut vec![])
+    }
+
+    /// Same as [`Self::route_all`], but leaves the nodes explored for each connection in
+    /// `visited`, which is resized to the number of connections.
+    pub fn search_all(
+        &mut self,
+        connections: &[Connection],
+        visited: &mut Vec<VisitedMap>,
+    ) -> Vec<Result<Route, RouteError>> {
+        visited.clear();
+        visited.resize_with(connections.len(), VisitedMap::new);
+        let mut routes: Vec<_> = connections
+            .iter()
+            .zip(visited.iter_mut())
+            .enumerate()
+            .map(|(i, (connection, visited))| {
+                let route = self.search(i, connection, visited);
+                if let Ok(route) = &route {
+                    self.add_route(route);
+                }
+                route
+            })
+            .collect();
+
+        if self.config.strategy == RoutingStrategy::Negotiated {
+            self.negotiate(connections, visited, &mut routes);
+        }
+        self.assign_ports(connections, visited, &mut routes);
+
+        routes
+    }
+
+    /// Rip up and reroute every connection until no grid edge is shared or the pass budget
+    /// runs out, and keep the best pass. The stubs straight out of the connectors are not
+    /// counted as shared, since routes from the same port cannot avoid sharing them.
+    fn negotiate(
+        &mut self,
+        connections: &[Connection],
+        visited: &mut [VisitedMap],
+        routes: &mut [Result<Route, RouteError>],
+    ) {
+        let stub_edges: HashSet<_> = self
+            .con_rects
+            .iter()
+            .flat_map(|rect| rect.connectors())
+            .flat_map(|connector| {
+                let (nodes, end) = self.stub_walk(connector);
+                let path: Vec<_> = std::iter::once(connector).chain(nodes).chain(end).collect();
+                path.windows(2)
+                    .map(|w| edge_key(w[0], w[1]))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let overused = |router: &Self| -> Vec<((usize, usize), u32)> {
+            router
+                .used_edges
+                .iter()
+                .filter(|(edge, count)| 1 < **count && !stub_edges.contains(edge))
+                .map(|(edge, count)| (*edge, *count))
+                .collect()
+        };
+        // Failed routes, then shared edges, then crossings, then length: lower is better
+        let score = |router: &Self, routes: &[Result<Route, RouteError>]| {
+            let failed = routes.iter().filter(|route| route.is_err()).count();
+            let shared: u32 = overused(router).iter().map(|(_, count)| count - 1).sum();
+            let length: f32 = routes
+                .iter()
+                .flatten()
+                .flat_map(|route| route.polyline.windows(2))
+                .map(|w| w[0].distance(w[1]))
+                .sum();
+            (failed, shared, router.crossings(routes), length)
+        };
+        let better = |a: &(usize, u32, usize, f32), b: &(usize, u32, usize, f32)| {
+            (a.0, a.1, a.2)
+                .cmp(&(b.0, b.1, b.2))
+                .then(a.3.total_cmp(&b.3))
+                .is_lt()
+        };
+
+        // The raised costs only steer the passes; later searches see the usual costs again.
+        let costs = (self.history.clone(), self.present_factor);
+        let mut best = (score(self, routes), routes.to_vec(), visited.to_vec());
+        for _ in 0..self.config.negotiation_iterations {
+            let overused = overused(self);
+            if overused.is_empty() {
+                break;
+            }
+            for (edge, count) in overused {
+                *self.history.entry(edge).or_default() +=
+                    self.config.history_cost * (count - 1) as f32;
+            }
+            self.present_factor *= PRESENT_FACTOR_GROWTH;
+
+            for (i, connection) in connections.iter().enumerate() {
+                if let Ok(route) = &routes[i] {
+                    self.remove_route(route);
+                }
+                visited[i].clear();
+                routes[i] = self.search(i, connection, &mut visited[i]);
+                if let Ok(route) = &routes[i] {
+                    self.add_route(route);
                 }
             }
 
-            // println!("Obstructed: {obstructed:?}");
+            let pass_score = score(self, routes);
+            if better(&pass_score, &best.0) {
+                best = (pass_score, routes.to_vec(), visited.to_vec());
+            }
+        }
 
-            let mut iter = 0;
+        let (_, best_routes, best_visited) = best;
+        for route in routes.iter().flatten() {
+            self.remove_route(route);
+        }
+        for route in best_routes.iter().flatten() {
+            self.add_route(route);
+        }
+        routes.clone_from_slice(&best_routes);
+        visited.clone_from_slice(&best_visited);
+        (self.history, self.present_factor) = costs;
+    }
 
-            while let Some(s_node) = next_set.pop() {
-                if self.goal_nodes.iter().any(|goal| *goal == s_node.id) {
-                    let mut path = vec![s_node.id];
-                    let mut prev = s_node.came_from;
-                    while let Some(came_from) = prev {
-                        path.push(came_from);
-                        prev = visited.get(&came_from).and_then(|node| node.came_from);
-                        iter += 1;
-                        if 1000 < iter {
-                            self.visited_nodes = Some(visited);
-                            return Err("Path find iteration exceeds 1000".to_string());
+    /// On each rect side with several ports, hand the ports used there out again to the
+    /// same routes, ordered by where they turn after leaving the side, and reroute them.
+    /// Routes turning the same way then nest instead of crossing next to the rect. The new
+    /// assignment is only kept if it reduces the number of crossings.
+    fn assign_ports(
+        &mut self,
+        connections: &[Connection],
+        visited: &mut [VisitedMap],
+        routes: &mut [Result<Route, RouteError>],
+    ) {
+        let con_rects = self.con_rects;
+        for (rect_id, rect) in con_rects.iter().enumerate() {
+            for side in Side::ALL {
+                let ports = rect.side_connectors(&[side]);
+                if ports.len() < 2 {
+                    continue;
+                }
+                // Routes ending at a port on this side, whether it is their start and the port
+                let ends: Vec<(usize, bool, usize)> = routes
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, route)| {
+                        let path = &route.as_ref().ok()?.path;
+                        let (first, last) = (*path.first()?, *path.last()?);
+                        if connections[i].from == rect_id && ports.contains(&first) {
+                            Some((i, true, first))
+                        } else if connections[i].to == rect_id && ports.contains(&last) {
+                            Some((i, false, last))
+                        } else {
+                            None
                         }
+                    })
+                    .collect();
+                if ends.len() < 2 {
+                    continue;
+                }
+
+                let along = |id: usize| {
+                    let pos = self.grid.points[id].pos;
+                    match side {
+                        Side::Left | Side::Right => pos.y,
+                        Side::Top | Side::Bottom => pos.x,
+                    }
+                };
+                let mut used: Vec<_> = ends.iter().map(|end| end.2).collect();
+                used.sort_by(|a, b| along(*a).total_cmp(&along(*b)));
+                let mut order = ends.clone();
+                order.sort_by(|a, b| {
+                    let key_a = self.turn_key(side, &routes[a.0], a.1);
+                    let key_b = self.turn_key(side, &routes[b.0], b.1);
+                    key_a.0.cmp(&key_b.0).then(key_a.1.total_cmp(&key_b.1))
+                });
+                if order.iter().map(|end| end.2).eq(used.iter().copied()) {
+                    continue;
+                }
+
+                let before = self.crossings(routes);
+                let mut old = vec![];
+                for &(i, _, _) in &ends {
+                    if let Ok(route) = &routes[i] {
+                        self.remove_route(route);
                     }
-                    println!("Path found! {path:?}");
-                    self.visited_nodes = Some(visited);
-                    self.path = Some(path);
-                    return Ok(());
-                }
-                let this_node = self.grid.points[s_node.id].pos;
-                let node = &self.grid.points[s_node.id];
-                for con in &node.connect {
-                    if obstructed.contains(con) {
+                    old.push((i, routes[i].clone(), std::mem::take(&mut visited[i])));
+                }
+                for (&(i, start, _), port) in order.iter().zip(used) {
+                    let Ok(route) = &routes[i] else {
                         continue;
+                    };
+                    let (from, to) = if start {
+                        (port, route.path[route.path.len() - 1])
+                    } else {
+                        (route.path[0], port)
+                    };
+                    routes[i] = self.search_between(
+                        i,
+                        &connections[i],
+                        &[(from, 0.)],
+                        &[(to, 0.)],
+                        &mut visited[i],
+                    );
+                    if let Ok(route) = &routes[i] {
+                        self.add_route(route);
+                    }
+                }
+
+                let all_routed = ends.iter().all(|(i, _, _)| routes[*i].is_ok());
+                if all_routed && self.crossings(routes) < before {
+                    continue;
+                }
+                for (i, route, old_visited) in old {
+                    if let Ok(route) = &routes[i] {
+                        self.remove_route(route);
+                    }
+                    if let Ok(route) = &route {
+                        self.add_route(route);
                     }
-                    let new_node = self.grid.points[*con].pos;
-                    let new_cost = s_node.cost + this_node.distance(new_node);
-                    visited
-                        .entry(*con)
-                        .and_modify(|e| {
-                            if new_cost < e.cost {
-                                e.cost = new_cost;
-                                e.came_from = Some(s_node.id);
-                                let new_node = SearchNode {
-                                    id: *con,
-                                    cost: new_cost,
-                                    came_from: Some(s_node.id),
-                                };
-                                // println!("Adding {new_node:?}");
-                                next_set.push(new_node);
-                            }
-                        })
-                        .or_insert_with(|| {
+                    routes[i] = route;
+                    visited[i] = old_visited;
+                }
+            }
+        }
+    }
+
+    /// Sort key of a route leaving a port on `side`: the direction along the side it turns
+    /// to first, then the distance from the side to that turn. The nearer a route turns,
+    /// the further it should start towards the direction it turns to.
+    fn turn_key(&self, side: Side, route: &Result<Route, RouteError>, start: bool) -> (i32, f32) {
+        let Ok(route) = route else {
+            return (0, 0.);
+        };
+        let mut polyline = route.polyline.clone();
+        if !start {
+            polyline.reverse();
+        }
+        let [port, corner, next, ..] = polyline[..] else {
+            return (0, 0.);
+        };
+        let turn = match side {
+            Side::Left | Side::Right => next.y - corner.y,
+            Side::Top | Side::Bottom => next.x - corner.x,
+        };
+        let turn = if turn < 0. { -1 } else { 1 };
+        (turn, -(turn as f32) * port.distance(corner))
+    }
+
+    fn crossings(&self, routes: &[Result<Route, RouteError>]) -> usize {
+        let polylines: Vec<_> = routes
+            .iter()
+            .flatten()
+            .map(|route| route.polyline.clone())
+            .collect();
+        count_crossings(&polylines)
+    }
+
+    /// Make later searches avoid crossing or overlapping `route`.
+    pub fn add_route(&mut self, route: &Route) {
+        for edge in self.route_edges(route) {
+            *self.used_edges.entry(edge).or_default() += 1;
+        }
+        for key in self.route_passing(route) {
+            *self.passing.entry(key).or_default() += 1;
+        }
+    }
+
+    /// Undo [`Self::add_route`].
+    pub fn remove_route(&mut self, route: &Route) {
+        for edge in self.route_edges(route) {
+            decrement(&mut self.used_edges, edge);
+        }
+        for key in self.route_passing(route) {
+            decrement(&mut self.passing, key);
+        }
+    }
+
+    fn route_edges(&self, route: &Route) -> Vec<(usize, usize)> {
+        route
+            .path
+            .windows(2)
+            .map(|w| edge_key(w[0], w[1]))
+            .collect()
+    }
+
+    /// Nodes the route goes straight through, with the axis it goes along.
+    fn route_passing(&self, route: &Route) -> Vec<(usize, Axis)> {
+        let pos = |i: usize| self.grid.points[i].pos;
+        route
+            .path
+            .windows(3)
+            .filter_map(|w| {
+                let axis = Axis::between(pos(w[0]), pos(w[1]));
+                (axis == Axis::between(pos(w[1]), pos(w[2]))).then_some((w[1], axis))
+            })
+            .collect()
+    }
+
+    /// Route a single connection. `connection_id` is only used to identify it in errors.
+    pub fn route(
+        &self,
+        connection_id: usize,
+        connection: &Connection,
+    ) -> Result<Route, RouteError> {
+        self.search(connection_id, connection, &mut VisitedMap::new())
+    }
+
+    /// Same as [`Self::route`], but leaves the explored nodes in `visited` for inspection.
+    pub fn search(
+        &self,
+        connection_id: usize,
+        connection: &Connection,
+        visited: &mut VisitedMap,
+    ) -> Result<Route, RouteError> {
+        if connection.from == connection.to {
+            return Err(RouteError::SelfLoop {
+                connection: connection_id,
+                rect: connection.from,
+            });
+        }
+        let starts =
+            self.endpoint_connectors(connection_id, connection.from, &connection.from_sides)?;
+        let goals = self.endpoint_connectors(connection_id, connection.to, &connection.to_sides)?;
+        self.search_between(connection_id, connection, &starts, &goals, visited)
+    }
+
+    /// Search from any of `starts` to any of `goals`, which are connector ids with the extra
+    /// cost of starting or ending there.
+    fn search_between(
+        &self,
+        connection_id: usize,
+        connection: &Connection,
+        starts: &[(usize, f32)],
+        goals: &[(usize, f32)],
+        visited: &mut VisitedMap,
+    ) -> Result<Route, RouteError> {
+        let goal_pos: Vec<_> = goals
+            .iter()
+            .map(|(id, _)| self.grid.points[*id].pos)
+            .collect();
+        let heuristic = |pos: Point| match self.config.algorithm {
+            SearchAlgorithm::Dijkstra => 0.,
+            SearchAlgorithm::AStar => goal_pos
+                .iter()
+                .map(|goal| (goal.x - pos.x).abs() + (goal.y - pos.y).abs())
+                .fold(f32::INFINITY, f32::min),
+        };
+        let endpoints = || starts.iter().chain(goals).map(|(id, _)| *id);
+        let stubs = self.stub_nodes(endpoints());
+        // Routes leave and enter their connectors straight out from the side, never from
+        // the gap between the connector and its rect.
+        let inward: HashSet<_> = endpoints()
+            .flat_map(|connector| {
+                let outward = self.outward_neighbour(connector);
+                self.grid.points[connector]
+                    .connect
+                    .iter()
+                    .filter(move |id| Some(**id) != outward)
+                    .map(move |id| edge_key(connector, *id))
+            })
+            .collect();
+        let mut next_set = BinaryHeap::new();
+        for &(start_id, penalty) in starts {
+            let state = SearchState::new(start_id, None);
+            next_set.push(SearchNode {
+                state,
+                cost: penalty,
+                priority: penalty + heuristic(self.grid.points[start_id].pos),
+                came_from: None,
+                arrived: false,
+            });
+            visited.insert(state, VisitedNode::new(penalty, None));
+        }
+
+        let limit = self.config.iteration_limit * self.grid.points.len();
+        let mut iter = 0;
+
+        while let Some(s_node) = next_set.pop() {
+            let goal_penalty = if s_node.arrived {
+                Some(0.)
+            } else if visited
+                .get(&s_node.state)
+                .is_some_and(|e| e.cost < s_node.cost)
+            {
+                // Stale entry superseded by a cheaper one
+                continue;
+            } else {
+                goals
+                    .iter()
+                    .find(|(id, _)| *id == s_node.state.id)
+                    .map(|(_, penalty)| *penalty)
+            };
+            if let Some(penalty) = goal_penalty.filter(|penalty| 0. < *penalty) {
+                // Entering through a less preferred side: only finish once the penalty is
+                // paid, in case a route to a better side turns out cheaper.
+                next_set.push(SearchNode {
+                    cost: s_node.cost + penalty,
+                    priority: s_node.cost + penalty,
+                    arrived: true,
+                    ..s_node
+                });
+            } else if goal_penalty.is_some() {
+                let mut path = vec![s_node.state.id];
+                let mut prev = s_node.came_from;
+                while let Some(came_from) = prev {
+                    path.push(came_from.id);
+                    prev = visited.get(&came_from).and_then(|node| node.came_from);
+                    iter += 1;
+                    if limit < iter {
+                        return Err(RouteError::IterationLimit {
+                            connection: connection_id,
+                            limit,
+                        });
+                    }
+                }
+                path.reverse();
+                return Ok(Route::new(path, self.grid));
+            }
+            let this_node = self.grid.points[s_node.state.id].pos;
+            let node = &self.grid.points[s_node.state.id];
+            for con in &node.connect {
+                if self.obstructed.contains(con) {
+                    continue;
+                }
+                let new_node = self.grid.points[*con].pos;
+                let axis = Axis::between(this_node, new_node);
+                let bend = s_node.state.axis.is_some_and(|prev| prev != axis);
+                let edge = edge_key(s_node.state.id, *con);
+                if bend && stubs.contains(&s_node.state.id) || inward.contains(&edge) {
+                    continue;
+                }
+                let overlaps = self.used_edges.get(&edge).copied().unwrap_or(0);
+                let history = self.history.get(&edge).copied().unwrap_or(0.);
+                // Going straight through a node that an earlier route goes straight through
+                // the other way is a crossing.
+                let crossings = if s_node.state.axis == Some(axis) {
+                    self.passing
+                        .get(&(s_node.state.id, axis.perpendicular()))
+                        .copied()
+                        .unwrap_or(0)
+                } else {
+                    0
+                };
+                let new_cost = s_node.cost
+                    + this_node.distance(new_node)
+                    + if bend { self.config.bend_penalty } else { 0. }
+                    + overlaps as f32 * self.config.overlap_penalty * self.present_factor
+                    + history
+                    + crossings as f32 * self.config.crossing_penalty;
+                let new_state = SearchState::new(*con, Some(axis));
+                let new_priority = new_cost + heuristic(new_node);
+                visited
+                    .entry(new_state)
+                    .and_modify(|e| {
+                        if new_cost < e.cost {
+                            e.cost = new_cost;
+                            e.came_from = Some(s_node.state);
                             let new_node = SearchNode {
-                                id: *con,
+                                state: new_state,
                                 cost: new_cost,
-                                came_from: Some(s_node.id),
+                                priority: new_priority,
+                                came_from: Some(s_node.state),
+                                arrived: false,
                             };
                             // println!("Adding {new_node:?}");
                             next_set.push(new_node);
-                            VisitedNode::new(new_cost, Some(s_node.id))
-                        });
+                        }
+                    })
+                    .or_insert_with(|| {
+                        let new_node = SearchNode {
+                            state: new_state,
+                            cost: new_cost,
+                            priority: new_priority,
+                            came_from: Some(s_node.state),
+                            arrived: false,
+                        };
+                        // println!("Adding {new_node:?}");
+                        next_set.push(new_node);
+                        VisitedNode::new(new_cost, Some(s_node.state))
+                    });
+            }
+            iter += 1;
+            if limit < iter {
+                return Err(RouteError::IterationLimit {
+                    connection: connection_id,
+                    limit,
+                });
+            }
+        }
+        Err(RouteError::NoPath {
+            connection: connection_id,
+            from: connection.from,
+            to: connection.to,
+        })
+    }
+
+    /// Connectors on `sides` of the rect at `rect_id` that a route can start or end at,
+    /// with the penalty for the rank of their side.
+    fn endpoint_connectors(
+        &self,
+        connection_id: usize,
+        rect_id: usize,
+        sides: &[Side],
+    ) -> Result<Vec<(usize, f32)>, RouteError> {
+        let rect = self.con_rects.get(rect_id).ok_or(RouteError::UnknownRect {
+            connection: connection_id,
+            rect: rect_id,
+        })?;
+        let connectors: Vec<_> = if sides.is_empty() {
+            rect.connectors().into_iter().map(|id| (id, 0.)).collect()
+        } else {
+            sides
+                .iter()
+                .enumerate()
+                .flat_map(|(rank, side)| {
+                    let penalty = rank as f32 * self.config.side_penalty;
+                    rect.side_connectors(&[*side])
+                        .into_iter()
+                        .map(move |id| (id, penalty))
+                })
+                .collect()
+        };
+        let Some(&(first, _)) = connectors.first() else {
+            if !sides.is_empty()
+                && sides
+                    .iter()
+                    .all(|side| rect.ports(*side).fractions().is_empty())
+            {
+                return Err(RouteError::NoPortsOnSides {
+                    connection: connection_id,
+                    rect: rect_id,
+                });
+            }
+            return Err(RouteError::EndpointOutsideGrid {
+                connection: connection_id,
+                rect: rect_id,
+            });
+        };
+        let free: Vec<_> = connectors
+            .into_iter()
+            .filter(|(id, _)| !self.obstructed.contains(id))
+            .collect();
+        if free.is_empty() {
+            let pos = self.grid.points[first].pos;
+            let obstacle = self
+                .con_rects
+                .iter()
+                .position(|other| other.contains(pos, self.config.collision_margin))
+                .unwrap_or(rect_id);
+            return Err(RouteError::EndpointInsideObstacle {
+                connection: connection_id,
+                rect: rect_id,
+                obstacle,
+            });
+        }
+        Ok(free)
+    }
+
+    /// Nodes straight out from `connectors` that are closer than `min_stub_length` to the
+    /// side of their rect. Routes must not turn there.
+    fn stub_nodes(&self, connectors: impl Iterator<Item = usize>) -> HashSet<usize> {
+        connectors
+            .flat_map(|connector| self.stub_walk(connector).0)
+            .collect()
+    }
+
+    /// Nodes straight out from `connector` that are closer than `min_stub_length` to the
+    /// side of its rect, and the first node after them, where routes may turn.
+    fn stub_walk(&self, connector: usize) -> (Vec<usize>, Option<usize>) {
+        let stub = self.config.min_stub_length - self.config.port_offset;
+        let port = self.grid.points[connector].pos;
+        let mut nodes = vec![];
+        let mut next = self.outward_neighbour(connector);
+        while let Some(node) = next {
+            let pos = self.grid.points[node].pos;
+            if stub <= port.distance(pos) + STUB_TOLERANCE {
+                return (nodes, Some(node));
+            }
+            nodes.push(node);
+            next = self.outward_neighbour_of(node, self.connector_sides[&connector]);
+        }
+        (nodes, None)
+    }
+
+    /// The neighbour of `connector` straight out from the side of its rect.
+    fn outward_neighbour(&self, connector: usize) -> Option<usize> {
+        let side = *self.connector_sides.get(&connector)?;
+        self.outward_neighbour_of(connector, side)
+    }
+
+    /// The neighbour of `node` straight out from `side`, i.e. further left of a left side.
+    fn outward_neighbour_of(&self, node: usize, side: Side) -> Option<usize> {
+        let pos = self.grid.points[node].pos;
+        self.grid.points[node].connect.iter().copied().find(|id| {
+            let next = self.grid.points[*id].pos;
+            let (along, across) = match side {
+                Side::Left => (pos.x - next.x, next.y - pos.y),
+                Side::Right => (next.x - pos.x, next.y - pos.y),
+                Side::Top => (pos.y - next.y, next.x - pos.x),
+                Side::Bottom => (next.y - pos.y, next.x - pos.x),
+            };
+            0. < along && across.abs() < STUB_TOLERANCE
+        })
+    }
+}
+
+fn edge_key(a: usize, b: usize) -> (usize, usize) {
+    (a.min(b), a.max(b))
+}
+
+fn decrement<K: std::hash::Hash + Eq>(map: &mut HashMap<K, u32>, key: K) {
+    if let std::collections::hash_map::Entry::Occupied(mut entry) = map.entry(key) {
+        *entry.get_mut() -= 1;
+        if *entry.get() == 0 {
+            entry.remove();
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::PortLayout;
+
+    /// Rects in a jittered 4 x 3 layout that never overlap, with connections between
+    /// pseudo-random pairs of them.
+    pub(crate) fn random_diagram(seed: u32) -> (Vec<ConRect>, Vec<Connection>) {
+        let mut state = seed.wrapping_mul(747_796_405).wrapping_add(2_891_336_453);
+        let mut next = |n: u32| {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (state >> 16) % n
+        };
+        let rects: Vec<_> = (0..12)
+            .map(|i| {
+                ConRect::new(
+                    (i % 4 * 200 + next(80)) as f32,
+                    (i / 4 * 160 + next(60)) as f32,
+                    (40 + next(60)) as f32,
+                    (30 + next(40)) as f32,
+                )
+            })
+            .collect();
+        let connections = (0..8)
+            .map(|_| {
+                let from = next(12) as usize;
+                let to = (from + 1 + next(11) as usize) % 12;
+                Connection::new(from, to)
+            })
+            .collect();
+        (rects, connections)
+    }
+
+    /// Cost the search found for `route`, read from the visited node it ended at.
+    fn route_cost(grid: &Grid, route: &Route, visited: &VisitedMap) -> f32 {
+        let [.., before, last] = route.path[..] else {
+            return 0.;
+        };
+        let axis = Axis::between(grid.points[before].pos, grid.points[last].pos);
+        visited[&SearchState::new(last, Some(axis))].cost
+    }
+
+    #[test]
+    fn default_config_routes_random_diagrams() {
+        for algorithm in [SearchAlgorithm::AStar, SearchAlgorithm::Dijkstra] {
+            let config = RouterConfig {
+                algorithm,
+                ..RouterConfig::default()
+            };
+            for seed in 0..30 {
+                let (mut rects, connections) = random_diagram(seed);
+                let grid = Grid::new(&mut rects, &config);
+                let routes = Router::new(&grid, &rects, config).route_all(&connections);
+                for route in routes {
+                    if let Err(err) = route {
+                        panic!("{algorithm:?}, seed {seed}: {err}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn a_star_finds_routes_as_cheap_as_dijkstra() {
+        for seed in 0..10 {
+            let (mut rects, connections) = random_diagram(seed);
+            let config = RouterConfig::default();
+            let grid = Grid::new(&mut rects, &config);
+            for (i, connection) in connections.iter().enumerate() {
+                let cost = |algorithm| {
+                    let router = Router::new(
+                        &grid,
+                        &rects,
+                        RouterConfig {
+                            algorithm,
+                            ..config
+                        },
+                    );
+                    let mut visited = VisitedMap::new();
+                    let route = router.search(i, connection, &mut visited).unwrap();
+                    (route_cost(&grid, &route, &visited), visited.len())
+                };
+                let (dijkstra, dijkstra_visited) = cost(SearchAlgorithm::Dijkstra);
+                let (a_star, a_star_visited) = cost(SearchAlgorithm::AStar);
+                assert!(
+                    (dijkstra - a_star).abs() < 1e-3,
+                    "seed {seed}, connection {i}: A* {a_star}, Dijkstra {dijkstra}"
+                );
+                assert!(a_star_visited <= dijkstra_visited);
+            }
+        }
+    }
+
+    /// Distance of `pos` out from `side` of `rect`, negative inside the rect.
+    fn distance_from_side(rect: &ConRect, side: Side, pos: Point) -> f32 {
+        match side {
+            Side::Left => rect.x - pos.x,
+            Side::Right => pos.x - (rect.x + rect.width),
+            Side::Top => rect.y - pos.y,
+            Side::Bottom => pos.y - (rect.y + rect.height),
+        }
+    }
+
+    #[test]
+    fn routes_leave_and_enter_straight_out_from_the_side() {
+        for min_stub_length in [RouterConfig::default().min_stub_length, 30.] {
+            let config = RouterConfig {
+                min_stub_length,
+                ..RouterConfig::default()
+            };
+            for seed in 0..10 {
+                let (mut rects, connections) = random_diagram(seed);
+                let grid = Grid::new(&mut rects, &config);
+                let routes = Router::new(&grid, &rects, config).route_all(&connections);
+                for (i, (connection, route)) in connections.iter().zip(&routes).enumerate() {
+                    let route = route.as_ref().unwrap();
+                    let polyline = &route.polyline;
+                    let ends = [
+                        (connection.from, route.path[0], polyline[1]),
+                        (
+                            connection.to,
+                            *route.path.last().unwrap(),
+                            polyline[polyline.len() - 2],
+                        ),
+                    ];
+                    for (rect, connector, bend) in ends {
+                        let rect = &rects[rect];
+                        let (side, _) = rect.cons.iter().find(|(_, id)| *id == connector).unwrap();
+                        let distance = distance_from_side(rect, *side, bend);
+                        assert!(
+                            min_stub_length <= distance + STUB_TOLERANCE,
+                            "stub {min_stub_length}, seed {seed}, connection {i}: {side:?} stub to {bend:?} is {distance} long"
+                        );
+                    }
                 }
-                iter += 1;
-                if 1000 < iter {
-                    self.visited_nodes = Some(visited);
-                    return Err("Exceed 1000 iterations".to_string());
+            }
+        }
+    }
+
+    #[test]
+    fn route_polylines_are_axis_aligned() {
+        let config = RouterConfig::default();
+        for seed in 0..20 {
+            let (mut rects, connections) = random_diagram(seed);
+            let grid = Grid::new(&mut rects, &config);
+            let routes = Router::new(&grid, &rects, config).route_all(&connections);
+            for (i, route) in routes.iter().enumerate() {
+                let polyline = &route.as_ref().unwrap().polyline;
+                for w in polyline.windows(2) {
+                    assert!(
+                        w[0].x == w[1].x || w[0].y == w[1].y,
+                        "seed {seed}, connection {i}: {:?} to {:?}",
+                        w[0],
+                        w[1]
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn routes_use_the_allowed_sides_in_order() {
+        let mut rects = vec![
+            ConRect::new(0., 0., 60., 40.),
+            ConRect::new(300., 0., 60., 40.),
+        ];
+        let grid = Grid::new(&mut rects, &RouterConfig::default());
+        let sides = |side_penalty, connection: Connection| {
+            let config = RouterConfig {
+                side_penalty,
+                ..RouterConfig::default()
+            };
+            let route = Router::new(&grid, &rects, config)
+                .route_all(&[connection])
+                .remove(0)
+                .unwrap();
+            let side = |rect: &ConRect, connector| {
+                rect.cons
+                    .iter()
+                    .find(|(_, id)| *id == connector)
+                    .map(|(side, _)| *side)
+                    .unwrap()
+            };
+            (
+                side(&rects[0], route.path[0]),
+                side(&rects[1], *route.path.last().unwrap()),
+            )
+        };
+
+        // Leaving away from the target is allowed when it is the only side.
+        let connection = Connection::new(0, 1).with_sides(vec![Side::Left], vec![Side::Right]);
+        assert_eq!(sides(50., connection), (Side::Left, Side::Right));
+        // A preferred side wins over a shorter route to a less preferred one.
+        let connection =
+            Connection::new(0, 1).with_sides(vec![Side::Right], vec![Side::Top, Side::Left]);
+        assert_eq!(sides(1000., connection.clone()), (Side::Right, Side::Top));
+        // Without a penalty, the ranking does not matter.
+        assert_eq!(sides(0., connection), (Side::Right, Side::Left));
+    }
+
+    #[test]
+    fn negotiation_restores_the_edge_costs() {
+        let config = RouterConfig {
+            strategy: RoutingStrategy::Negotiated,
+            overlap_penalty: 0.,
+            crossing_penalty: 0.,
+            ..RouterConfig::default()
+        };
+        for seed in 0..5 {
+            let (mut rects, connections) = random_diagram(seed);
+            let grid = Grid::new(&mut rects, &config);
+            let mut router = Router::new(&grid, &rects, config);
+            let mut visited = vec![];
+            let routes = router.search_all(&connections, &mut visited);
+            assert!(router.history.is_empty(), "seed {seed}");
+            assert_eq!(router.present_factor, 1., "seed {seed}");
+
+            // The routes are registered with the router, so removing them leaves no edges.
+            for route in routes.iter().flatten() {
+                router.remove_route(route);
+            }
+            assert!(router.used_edges.is_empty(), "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn port_assignment_reduces_crossings() {
+        let config = RouterConfig::default();
+        let (mut total_before, mut total_after) = (0, 0);
+        for seed in 0..15 {
+            let (mut rects, connections) = random_diagram(seed);
+            for rect in &mut rects {
+                for side in Side::ALL {
+                    *rect.ports_mut(side) = PortLayout::Even(3);
                 }
             }
+            let grid = Grid::new(&mut rects, &config);
+            let mut router = Router::new(&grid, &rects, config);
+            let mut visited = vec![VisitedMap::new(); connections.len()];
+            let mut routes: Vec<_> = connections
+                .iter()
+                .zip(&mut visited)
+                .enumerate()
+                .map(|(i, (connection, visited))| {
+                    let route = router.search(i, connection, visited);
+                    if let Ok(route) = &route {
+                        router.add_route(route);
+                    }
+                    route
+                })
+                .collect();
+            let before = router.crossings(&routes);
+            router.assign_ports(&connections, &mut visited, &mut routes);
+            let after = router.crossings(&routes);
+            assert!(
+                after <= before,
+                "seed {seed}: {before} -> {after} crossings"
+            );
+            total_before += before;
+            total_after += after;
+
+            for (connection, route) in connections.iter().zip(&routes) {
+                let path = &route.as_ref().unwrap().path;
+                assert!(rects[connection.from].connectors().contains(&path[0]));
+                assert!(rects[connection.to]
+                    .connectors()
+                    .contains(path.last().unwrap()));
+            }
         }
-        self.visited_nodes = None;
-        Ok(())
+        assert!(
+            total_after < total_before,
+            "{total_before} -> {total_after}"
+        );
     }
 }
@@ -0,0 +1,352 @@
+//! How connectors are drawn. The router only produces polylines; this module turns them
+//! into outlines of straight lines and cubic Bézier curves that every renderer draws the
+//! same way.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::geom::Point;
+
+/// Distance of the control points from the ends of a cubic Bézier curve approximating a
+/// quarter circle, relative to its radius
+const ARC_KAPPA: f32 = 0.552_284_8;
+
+/// Number of straight lines each curve is approximated with by [`flatten`]
+const CURVE_STEPS: usize = 16;
+
+/// Size of the end markers along the line and across it
+const MARKER_LENGTH: f32 = 10.;
+const MARKER_WIDTH: f32 = 8.;
+
+/// Drawing settings of a single connection.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ConnectionStyle {
+    pub shape: LineShape,
+    pub pattern: LinePattern,
+    /// Marker at the `from` end
+    pub start_marker: Marker,
+    /// Marker at the `to` end
+    pub end_marker: Marker,
+    /// Where along the route the connection's label goes
+    pub label_position: LabelPosition,
+}
+
+impl ConnectionStyle {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Shapes of the start and end markers at the ends of `polyline`.
+    pub fn markers(&self, polyline: &[Point]) -> Vec<MarkerShape> {
+        let mut shapes = vec![];
+        if let [first, second, ..] = polyline[..] {
+            shapes.extend(self.start_marker.shape(second, first));
+        }
+        if let [.., second_last, last] = polyline[..] {
+            shapes.extend(self.end_marker.shape(second_last, last));
+        }
+        shapes
+    }
+}
+
+/// Shape of the line drawn along a connector's polyline.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum LineShape {
+    /// Straight segments with sharp corners
+    #[default]
+    Sharp,
+    /// Straight segments joined by quarter circles. The radius shrinks where segments are
+    /// too short for it.
+    Rounded { radius: f32 },
+    /// A smooth curve through the ends and bends of the polyline
+    Spline,
+}
+
+/// Dash pattern of a connector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum LinePattern {
+    #[default]
+    Solid,
+    Dashed,
+    Dotted,
+}
+
+impl LinePattern {
+    /// Lengths of the dashes and of the gaps between them, `None` for a solid line.
+    pub fn dashes(self) -> Option<(f32, f32)> {
+        match self {
+            Self::Solid => None,
+            Self::Dashed => Some((8., 4.)),
+            Self::Dotted => Some((2., 4.)),
+        }
+    }
+}
+
+/// Preferred place of a connection label along its route. The label moves along the route
+/// from there if it would overlap a rect or another label.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum LabelPosition {
+    /// Middle of the longest straight segment
+    #[default]
+    LongestSegment,
+    /// Near the `from` end
+    Start,
+    /// Halfway along the route
+    Middle,
+    /// Near the `to` end
+    End,
+}
+
+/// Decoration at an end of a connector, pointing along its last segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Marker {
+    #[default]
+    None,
+    /// Filled triangle
+    Arrow,
+    /// Two lines forming a V
+    OpenArrow,
+    /// Filled diamond
+    Diamond,
+    /// Filled circle
+    Circle,
+}
+
+/// Outline of a [`Marker`], drawn in the colour of the connector.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MarkerShape {
+    /// Filled convex polygon
+    Polygon(Vec<Point>),
+    /// Open polyline, stroked like the connector
+    Polyline(Vec<Point>),
+    /// Filled circle
+    Circle { center: Point, radius: f32 },
+}
+
+impl Marker {
+    /// Outline of this marker at `tip`, the end of a line coming from `from`.
+    pub fn shape(self, from: Point, tip: Point) -> Option<MarkerShape> {
+        let length = from.distance(tip);
+        if self == Self::None || length == 0. {
+            return None;
+        }
+        let (dx, dy) = ((tip.x - from.x) / length, (tip.y - from.y) / length);
+        // The point `back` behind the tip along the line and `across` to the side of it
+        let at = |back: f32, across: f32| {
+            Point::new(
+                tip.x - dx * back - dy * across,
+                tip.y - dy * back + dx * across,
+            )
+        };
+        let (l, w) = (MARKER_LENGTH, MARKER_WIDTH / 2.);
+        Some(match self {
+            Self::None => return None,
+            Self::Arrow => MarkerShape::Polygon(vec![tip, at(l, w), at(l, -w)]),
+            Self::OpenArrow => MarkerShape::Polyline(vec![at(l, w), tip, at(l, -w)]),
+            Self::Diamond => {
+                MarkerShape::Polygon(vec![tip, at(l / 2., w), at(l, 0.), at(l / 2., -w)])
+            }
+            Self::Circle => MarkerShape::Circle {
+                center: at(w, 0.),
+                radius: w,
+            },
+        })
+    }
+}
+
+/// A step of an outline, like the commands of an SVG path.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathCommand {
+    MoveTo(Point),
+    LineTo(Point),
+    /// Cubic Bézier curve with two control points to the end point
+    CubicTo(Point, Point, Point),
+}
+
+impl LineShape {
+    /// Outline of a connector along `polyline` in this shape.
+    pub fn path(self, polyline: &[Point]) -> Vec<PathCommand> {
+        let Some(&first) = polyline.first() else {
+            return vec![];
+        };
+        let mut path = vec![PathCommand::MoveTo(first)];
+        match self {
+            Self::Sharp => path.extend(polyline[1..].iter().map(|p| PathCommand::LineTo(*p))),
+            Self::Rounded { radius } => {
+                let last = polyline.len() - 1;
+                for i in 1..last {
+                    let (prev, corner, next) = (polyline[i - 1], polyline[i], polyline[i + 1]);
+                    // Inner segments are shared with the neighbouring corners.
+                    let before = corner.distance(prev) / if i == 1 { 1. } else { 2. };
+                    let after = corner.distance(next) / if i + 1 == last { 1. } else { 2. };
+                    let r = radius.min(before).min(after).max(0.);
+                    if r == 0. {
+                        path.push(PathCommand::LineTo(corner));
+                        continue;
+                    }
+                    let start = lerp(corner, prev, r / corner.distance(prev));
+                    let end = lerp(corner, next, r / corner.distance(next));
+                    path.push(PathCommand::LineTo(start));
+                    path.push(PathCommand::CubicTo(
+                        lerp(start, corner, ARC_KAPPA),
+                        lerp(end, corner, ARC_KAPPA),
+                        end,
+                    ));
+                }
+                if 0 < last {
+                    path.push(PathCommand::LineTo(polyline[last]));
+                }
+            }
+            Self::Spline => {
+                // The curve runs through each point parallel to the line between its
+                // neighbours, and leaves and enters along the first and last segments. The
+                // control points are a third of a segment away, so short segments such as
+                // the stubs at the connectors do not make it overshoot.
+                let last = polyline.len() - 1;
+                let tangent = |i: usize| {
+                    let (a, b) = (polyline[i.saturating_sub(1)], polyline[(i + 1).min(last)]);
+                    let length = a.distance(b);
+                    if length == 0. {
+                        Point::default()
+                    } else {
+                        Point::new((b.x - a.x) / length, (b.y - a.y) / length)
+                    }
+                };
+                for i in 1..=last {
+                    let (from, to) = (polyline[i - 1], polyline[i]);
+                    let handle = from.distance(to) / 3.;
+                    let (t0, t1) = (tangent(i - 1), tangent(i));
+                    path.push(PathCommand::CubicTo(
+                        Point::new(from.x + t0.x * handle, from.y + t0.y * handle),
+                        Point::new(to.x - t1.x * handle, to.y - t1.y * handle),
+                        to,
+                    ));
+                }
+            }
+        }
+        path
+    }
+}
+
+/// Approximate `path` with a polyline, for renderers that cannot draw curves.
+pub fn flatten(path: &[PathCommand]) -> Vec<Point> {
+    let mut points: Vec<Point> = vec![];
+    for command in path {
+        match *command {
+            PathCommand::MoveTo(p) | PathCommand::LineTo(p) => points.push(p),
+            PathCommand::CubicTo(c1, c2, end) => {
+                let start = points.last().copied().unwrap_or(c1);
+                points.extend(
+                    (1..=CURVE_STEPS)
+                        .map(|step| cubic(start, c1, c2, end, step as f32 / CURVE_STEPS as f32)),
+                );
+            }
+        }
+    }
+    points
+}
+
+fn lerp(a: Point, b: Point, t: f32) -> Point {
+    Point::new(a.x + (b.x - a.x) * t, a.y + (b.y - a.y) * t)
+}
+
+fn cubic(p0: Point, p1: Point, p2: Point, p3: Point, t: f32) -> Point {
+    let s = 1. - t;
+    let (a, b, c, d) = (s * s * s, 3. * s * s * t, 3. * s * t * t, t * t * t);
+    Point::new(
+        a * p0.x + b * p1.x + c * p2.x + d * p3.x,
+        a * p0.y + b * p1.y + c * p2.y + d * p3.y,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: Point, b: Point) {
+        assert!(a.distance(b) < 1e-3, "{a:?} is not {b:?}");
+    }
+
+    #[test]
+    fn rounded_corners_fit_short_segments() {
+        // The middle segment is too short for two corners of the full radius, so each
+        // corner gets half of it.
+        let polyline = [
+            Point::new(0., 0.),
+            Point::new(100., 0.),
+            Point::new(100., 6.),
+            Point::new(200., 6.),
+        ];
+        let path = LineShape::Rounded { radius: 20. }.path(&polyline);
+        let ends: Vec<_> = path
+            .iter()
+            .map(|command| match *command {
+                PathCommand::MoveTo(p) | PathCommand::LineTo(p) | PathCommand::CubicTo(_, _, p) => {
+                    p
+                }
+            })
+            .collect();
+        let expected = [
+            Point::new(0., 0.),
+            Point::new(97., 0.),
+            Point::new(100., 3.),
+            Point::new(100., 3.),
+            Point::new(103., 6.),
+            Point::new(200., 6.),
+        ];
+        assert_eq!(ends.len(), expected.len());
+        for (end, expected) in ends.into_iter().zip(expected) {
+            assert_near(end, expected);
+        }
+        assert!(matches!(path[2], PathCommand::CubicTo(..)));
+        assert!(matches!(path[4], PathCommand::CubicTo(..)));
+    }
+
+    #[test]
+    fn arrow_points_along_the_last_segment() {
+        let style = ConnectionStyle {
+            end_marker: Marker::Arrow,
+            ..ConnectionStyle::default()
+        };
+        let arrow = |polyline: &[Point]| match &style.markers(polyline)[..] {
+            [MarkerShape::Polygon(polygon)] => polygon.clone(),
+            markers => panic!("expected one arrow, got {markers:?}"),
+        };
+
+        let polygon = arrow(&[
+            Point::new(0., 0.),
+            Point::new(0., 50.),
+            Point::new(80., 50.),
+        ]);
+        assert_eq!(polygon[0], Point::new(80., 50.));
+        assert_near(
+            polygon[1],
+            Point::new(80. - MARKER_LENGTH, 50. + MARKER_WIDTH / 2.),
+        );
+        assert_near(
+            polygon[2],
+            Point::new(80. - MARKER_LENGTH, 50. - MARKER_WIDTH / 2.),
+        );
+
+        let polygon = arrow(&[Point::new(0., 0.), Point::new(0., -40.)]);
+        assert_eq!(polygon[0], Point::new(0., -40.));
+        assert_near(
+            polygon[1],
+            Point::new(MARKER_WIDTH / 2., MARKER_LENGTH - 40.),
+        );
+        assert_near(
+            polygon[2],
+            Point::new(-MARKER_WIDTH / 2., MARKER_LENGTH - 40.),
+        );
+    }
+}
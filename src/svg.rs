@@ -0,0 +1,179 @@
+//! SVG export of rects and routed connectors. It only produces text, so it works without
+//! a GPU or a window.
+
+use std::fmt::Write;
+
+use crate::{
+    geom::Point,
+    grid::bounding_box,
+    label::{place_labels, LabelPlacement, FONT_SIZE},
+    style::{MarkerShape, PathCommand},
+    ConRect, Connection,
+};
+
+/// Same colours and stroke widths as the egui demo.
+const RECT_COLOR: &str = "rgb(0, 0, 255)";
+const RECT_STROKE_WIDTH: f32 = 2.;
+const ROUTE_COLOR: &str = "rgb(255, 0, 0)";
+const ROUTE_STROKE_WIDTH: f32 = 2.;
+const LABEL_COLOR: &str = "rgb(0, 0, 0)";
+
+/// Space around the drawing inside the SVG view box.
+const VIEW_MARGIN: f32 = 10.;
+
+/// Render `con_rects` as `<rect>`s and the polyline of each connection as a `<path>` in
+/// the connection's style, with its label as a `<text>`. `polylines` are in the same order
+/// as `connections`, with `None` for connections that failed to route.
+pub fn to_svg(
+    con_rects: &[ConRect],
+    connections: &[Connection],
+    polylines: &[Option<Vec<Point>>],
+) -> String {
+    let mut svg = String::new();
+    write_svg(&mut svg, con_rects, connections, polylines)
+        .expect("writing to a String should not fail");
+    svg
+}
+
+pub fn write_svg(
+    out: &mut impl Write,
+    con_rects: &[ConRect],
+    connections: &[Connection],
+    polylines: &[Option<Vec<Point>>],
+) -> std::fmt::Result {
+    let routed: Vec<_> = connections
+        .iter()
+        .zip(polylines)
+        .filter_map(|(connection, polyline)| Some((&connection.style, polyline.as_ref()?)))
+        .collect();
+    let paths: Vec<_> = routed
+        .iter()
+        .map(|(style, polyline)| style.shape.path(polyline))
+        .collect();
+    let labels = place_labels(con_rects, connections, polylines);
+    let (min, max) = view_box(con_rects, &paths, &labels);
+    let width = max.x - min.x;
+    let height = max.y - min.y;
+    writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="{} {} {width} {height}">"#,
+        min.x, min.y
+    )?;
+
+    for ((style, polyline), path) in routed.iter().zip(&paths) {
+        let dashes = style
+            .pattern
+            .dashes()
+            .map(|(dash, gap)| format!(r#" stroke-dasharray="{dash} {gap}""#))
+            .unwrap_or_default();
+        writeln!(
+            out,
+            r#"  <path d="{}" fill="none" stroke="{ROUTE_COLOR}" stroke-width="{ROUTE_STROKE_WIDTH}"{dashes}/>"#,
+            path_data(path)
+        )?;
+        for marker in style.markers(polyline) {
+            write_marker(out, &marker)?;
+        }
+    }
+
+    for (connection, label) in connections.iter().zip(&labels) {
+        let (Some(text), Some(label)) = (&connection.label, label) else {
+            continue;
+        };
+        writeln!(
+            out,
+            r#"  <text x="{}" y="{}" font-size="{FONT_SIZE}" text-anchor="middle" dominant-baseline="central" fill="{LABEL_COLOR}">{}</text>"#,
+            label.center.x,
+            label.center.y,
+            escape(text)
+        )?;
+    }
+
+    for rect in con_rects {
+        writeln!(
+            out,
+            r#"  <rect x="{}" y="{}" width="{}" height="{}" fill="none" stroke="{RECT_COLOR}" stroke-width="{RECT_STROKE_WIDTH}"/>"#,
+            rect.x, rect.y, rect.width, rect.height
+        )?;
+    }
+
+    writeln!(out, "</svg>")
+}
+
+fn write_marker(out: &mut impl Write, marker: &MarkerShape) -> std::fmt::Result {
+    let points = |points: &[Point]| {
+        let points: Vec<_> = points
+            .iter()
+            .map(|pos| format!("{},{}", pos.x, pos.y))
+            .collect();
+        points.join(" ")
+    };
+    match marker {
+        MarkerShape::Polygon(polygon) => writeln!(
+            out,
+            r#"  <polygon points="{}" fill="{ROUTE_COLOR}"/>"#,
+            points(polygon)
+        ),
+        MarkerShape::Polyline(polyline) => writeln!(
+            out,
+            r#"  <polyline points="{}" fill="none" stroke="{ROUTE_COLOR}" stroke-width="{ROUTE_STROKE_WIDTH}"/>"#,
+            points(polyline)
+        ),
+        MarkerShape::Circle { center, radius } => writeln!(
+            out,
+            r#"  <circle cx="{}" cy="{}" r="{radius}" fill="{ROUTE_COLOR}"/>"#,
+            center.x, center.y
+        ),
+    }
+}
+
+/// The `d` attribute of a `<path>`.
+fn path_data(path: &[PathCommand]) -> String {
+    let commands: Vec<_> = path
+        .iter()
+        .map(|command| match command {
+            PathCommand::MoveTo(p) => format!("M {},{}", p.x, p.y),
+            PathCommand::LineTo(p) => format!("L {},{}", p.x, p.y),
+            PathCommand::CubicTo(c1, c2, p) => {
+                format!("C {},{} {},{} {},{}", c1.x, c1.y, c2.x, c2.y, p.x, p.y)
+            }
+        })
+        .collect();
+    commands.join(" ")
+}
+
+/// Bounding box of the rects and the paths, including the control points of curves, which
+/// enclose them.
+fn view_box(
+    con_rects: &[ConRect],
+    paths: &[Vec<PathCommand>],
+    labels: &[Option<LabelPlacement>],
+) -> (Point, Point) {
+    let (mut min, mut max) = bounding_box(con_rects).unwrap_or_default();
+    let points = paths.iter().flatten().flat_map(|command| match *command {
+        PathCommand::MoveTo(p) | PathCommand::LineTo(p) => vec![p],
+        PathCommand::CubicTo(c1, c2, p) => vec![c1, c2, p],
+    });
+    let label_corners = labels.iter().flatten().flat_map(|label| {
+        let (dx, dy) = (label.width / 2., label.height / 2.);
+        [
+            Point::new(label.center.x - dx, label.center.y - dy),
+            Point::new(label.center.x + dx, label.center.y + dy),
+        ]
+    });
+    for pos in points.chain(label_corners) {
+        min = Point::new(min.x.min(pos.x), min.y.min(pos.y));
+        max = Point::new(max.x.max(pos.x), max.y.max(pos.y));
+    }
+    (
+        Point::new(min.x - VIEW_MARGIN, min.y - VIEW_MARGIN),
+        Point::new(max.x + VIEW_MARGIN, max.y + VIEW_MARGIN),
+    )
+}
+
+/// Escape the characters that have a meaning in XML text.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}